@@ -14,21 +14,21 @@ struct EchoMessage {
 
 // Define output and error types for berevity.
 // The Output type must implement [`serde::Serialize`]
-type OUT = EchoMessage;
+type Out = EchoMessage;
 // The error type must implement the `Display` trait
-type ERR = String;
+type Error = String;
 
 // Implement an initialization function.
-// The initialization function returns a Result with the Ok type resolving to a dynamically allocated
+// The initialization function accepts an `InitContext` - which exposes the env-vars and lets you register hooks -
+// and returns a Result with the Ok type resolving to a dynamically allocated
 // closure that accepts the Event from Lambda (as an optional string) and the context object.
-// The closure itself returns a Result with the Ok and Err types being the previously defined `OUT` and `ERR` types respectively.
+// The closure itself returns a Result with the Ok and Err types being the previously defined `Out` and `Error` types respectively.
 // The initialization function may fail (e.g if a db connection was not succesfully opened, etc..) and in that case
-// the function should return an Err variant of the same `ERR` type defined for the event handler.
-fn initialize() -> Result<
-    Box<dyn Fn(Option<&str>, RefLambdaContext<LambdaRuntimeEnv, UreqResponse>) -> Result<OUT, ERR>>,
-    ERR,
-> {
-    return Ok(Box::new(move |event, context| {
+// the function should return an Err variant of the same `Error` type defined for the event handler.
+fn initialize(
+    _init: &mut InitContext<LambdaRuntimeEnv>,
+) -> Result<EventHandler<LambdaRuntimeEnv, UreqResponse, Out, Error>, Error> {
+    Ok(Box::new(move |event, context| {
         // Get the aws request id
         let req_id = context.aws_request_id().unwrap();
 
@@ -36,14 +36,15 @@ fn initialize() -> Result<
         let event = match event {
             Some(v) => v,
             None => {
-                return Err(format!(
+                return Err(
                     "AWS should not permit empty events. Something strange must've happened."
-                ))
+                        .to_string(),
+                )
             }
         };
 
         if event == "\"\"" {
-            return Err(format!("Empty input, nothing to echo."));
+            return Err("Empty input, nothing to echo.".to_string());
         }
 
         // Echo the event back as a string.
@@ -51,14 +52,14 @@ fn initialize() -> Result<
             msg: format!("ECHO: {}", event),
             req_id: req_id.to_string(),
         })
-    }));
+    }))
 }
 
 fn main() {
     // Create a runtime instance and run its loop.
     // This is the equivalent of:
-    // let mut runtime =  DefaultRuntime::<UreqResponse, UreqTransport, LambdaRuntimeEnv, OUT, ERR>::new(LAMBDA_VER, initialize);
-    let mut runtime = default_runtime!(OUT, ERR, LAMBDA_VER, initialize);
+    // let mut runtime =  DefaultRuntime::<UreqResponse, UreqTransport, LambdaRuntimeEnv, Out, Error>::new(LAMBDA_VER, initialize);
+    let mut runtime = default_runtime!(Out, Error, LAMBDA_VER, initialize);
 
     runtime.run();
 }
@@ -0,0 +1,196 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{FnArg, GenericArgument, ItemFn, Path, PathArguments, ReturnType, Type};
+
+/// The arguments of the `#[rtlambda::main(...)]` attribute.
+#[derive(Default)]
+pub(crate) struct Args {
+    init: Option<Path>,
+}
+
+impl Args {
+    pub(crate) fn parse(args: proc_macro::TokenStream) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("init") {
+                parsed.init = Some(meta.value()?.parse::<Path>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported argument, expected `init`"))
+            }
+        });
+        syn::parse::Parser::parse(parser, args)?;
+        Ok(parsed)
+    }
+}
+
+pub(crate) fn expand(args: &Args, handler: &ItemFn) -> syn::Result<TokenStream2> {
+    let sig = &handler.sig;
+    if sig.ident == "main" {
+        return Err(syn::Error::new_spanned(
+            &sig.ident,
+            "#[rtlambda::main] generates the `main` function, annotate the event handler instead",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "the event handler can't be generic",
+        ));
+    }
+    let (out, err) = result_types(&sig.output)?;
+
+    // The handler accepts the event, a reference to the state returned by the init function if any, and optionally the context.
+    let mut inputs = Vec::new();
+    for input in &sig.inputs {
+        match input {
+            FnArg::Typed(arg) => inputs.push(&*arg.ty),
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "the event handler can't be a method",
+                ))
+            }
+        }
+    }
+    let state_args = usize::from(args.init.is_some());
+    let expected = match args.init {
+        Some(_) => "`(event, &state)` or `(event, &state, context)`",
+        None => "`(event)` or `(event, context)`",
+    };
+    if inputs.is_empty() || inputs.len() > 2 + state_args {
+        return Err(syn::Error::new_spanned(
+            &sig.inputs,
+            format!("the event handler should accept {}", expected),
+        ));
+    }
+    if let Some(state) = inputs.get(state_args).filter(|_| state_args == 1) {
+        if !matches!(state, Type::Reference(_)) {
+            return Err(syn::Error::new_spanned(
+                state,
+                "the state returned by the init function is passed by reference",
+            ));
+        }
+    }
+    let context = inputs.get(1 + state_args).copied();
+
+    // `Option<&str>` events are passed as is, other types are deserialized from JSON.
+    let event = if is_raw_event(inputs[0]) {
+        quote!(event)
+    } else {
+        quote!(::rtlambda::runtime::entry::deserialize_event(event)?)
+    };
+    let mut call_args = vec![event];
+    if args.init.is_some() {
+        call_args.push(quote!(&state));
+    }
+    if context.is_some() {
+        call_args.push(quote!(context));
+    }
+    let name = &sig.ident;
+    let mut call = quote!(#name(#(#call_args),*));
+    let mut requirements = vec![quote!(::rtlambda::__require_ureq!();)];
+    if sig.asyncness.is_some() {
+        call = quote!(::rtlambda::runtime::entry::block_on(#call));
+        requirements.push(quote!(::rtlambda::__require_tokio!();));
+    }
+
+    let context_kind = if context.is_some_and(is_owned_context) {
+        quote!(::rtlambda::data::context::OwnedContextKind)
+    } else {
+        quote!(::rtlambda::data::context::RefContextKind)
+    };
+    let (init, state) = match &args.init {
+        Some(init) => (quote!(init), Some(quote!(let state = #init(init)?;))),
+        None => (quote!(_init), None),
+    };
+    let context = match context {
+        Some(_) => quote!(context),
+        None => quote!(_context),
+    };
+    Ok(quote! {
+        #handler
+
+        fn main() {
+            #(#requirements)*
+
+            fn __rtlambda_initialize(
+                #init: &mut ::rtlambda::runtime::init::InitContext<::rtlambda::data::env::LambdaRuntimeEnv>,
+            ) -> ::core::result::Result<
+                ::rtlambda::runtime::EventHandler<
+                    ::rtlambda::data::env::LambdaRuntimeEnv,
+                    ::rtlambda::backends::ureq::UreqResponse,
+                    #out,
+                    #err,
+                    #context_kind,
+                >,
+                #err,
+            > {
+                #state
+                ::core::result::Result::Ok(::std::boxed::Box::new(move |event, #context| {
+                    #call
+                }))
+            }
+
+            let mut runtime = ::rtlambda::runtime::DefaultRuntime::<
+                ::rtlambda::backends::ureq::UreqResponse,
+                ::rtlambda::backends::ureq::UreqTransport,
+                ::rtlambda::data::env::LambdaRuntimeEnv,
+                #out,
+                #err,
+                #context_kind,
+            >::new(::rtlambda::LAMBDA_VER, __rtlambda_initialize);
+            ::rtlambda::runtime::LambdaRuntime::run(&mut runtime);
+        }
+    })
+}
+
+/// Returns the `OUT` and `ERR` types of a `Result<OUT, ERR>` return type.
+fn result_types(output: &ReturnType) -> syn::Result<(&Type, &Type)> {
+    let error =
+        || syn::Error::new_spanned(output, "the event handler should return `Result<OUT, ERR>`");
+    let ReturnType::Type(_, ty) = output else {
+        return Err(error());
+    };
+    let Type::Path(path) = &**ty else {
+        return Err(error());
+    };
+    let segment = path.path.segments.last().ok_or_else(error)?;
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return Err(error());
+    };
+    let mut types = args.args.iter().filter_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    });
+    match (segment.ident == "Result", types.next(), types.next()) {
+        (true, Some(out), Some(err)) => Ok((out, err)),
+        _ => Err(error()),
+    }
+}
+
+/// Returns whether `ty` is `Option<&str>`.
+fn is_raw_event(ty: &Type) -> bool {
+    match crate::option_inner(ty) {
+        Some(Type::Reference(reference)) => {
+            matches!(&*reference.elem, Type::Path(path) if path.path.is_ident("str"))
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether `ty` is `OwnedLambdaContext`.
+fn is_owned_context(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "OwnedLambdaContext"),
+        _ => false,
+    }
+}
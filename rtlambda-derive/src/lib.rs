@@ -0,0 +1,273 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+//! Procedural macros for [rtlambda](https://crates.io/crates/rtlambda), re-exported by its `derive` and `macros` features.
+
+mod entry;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, GenericArgument, ItemFn, LitStr, PathArguments,
+    Type,
+};
+
+/// Implements `rtlambda::config::LambdaConfig` for a struct with named fields.
+///
+/// See the documentation of the `LambdaConfig` trait for the supported attributes.
+#[proc_macro_derive(LambdaConfig, attributes(lambda_config))]
+pub fn derive_lambda_config(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Generates a `main` function running the annotated event handler on the default runtime.
+///
+/// See the documentation of `rtlambda::main` for the supported handler signatures.
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, input: TokenStream) -> TokenStream {
+    let handler = parse_macro_input!(input as ItemFn);
+    entry::Args::parse(args)
+        .and_then(|args| entry::expand(&args, &handler))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// The options set using `#[lambda_config(...)]` attributes.
+#[derive(Default)]
+struct Options {
+    prefix: Option<String>,
+    env: Option<String>,
+    default: Option<String>,
+}
+
+impl Options {
+    fn parse(attrs: &[syn::Attribute], allowed: &[&str]) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("lambda_config"))
+        {
+            attr.parse_nested_meta(|meta| {
+                let key = meta
+                    .path
+                    .get_ident()
+                    .map(|ident| ident.to_string())
+                    .unwrap_or_default();
+                if !allowed.contains(&key.as_str()) {
+                    return Err(meta.error(format!(
+                        "unsupported attribute, expected one of: {}",
+                        allowed.join(", ")
+                    )));
+                }
+                let value = meta.value()?.parse::<LitStr>()?.value();
+                match key.as_str() {
+                    "prefix" => options.prefix = Some(value),
+                    "env" => options.env = Some(value),
+                    _ => options.default = Some(value),
+                }
+                Ok(())
+            })?;
+        }
+        Ok(options)
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "LambdaConfig can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "LambdaConfig can only be derived for structs",
+            ))
+        }
+    };
+    let prefix = Options::parse(&input.attrs, &["prefix"])?
+        .prefix
+        .unwrap_or_default();
+
+    let mut reads = Vec::new();
+    let mut inits = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named fields have identifiers");
+        let options = Options::parse(&field.attrs, &["env", "default"])?;
+        let var = format!(
+            "{}{}",
+            prefix,
+            options
+                .env
+                .unwrap_or_else(|| ident.to_string().to_uppercase())
+        );
+        let default = match options.default {
+            Some(default) => quote!(::core::option::Option::Some(#default)),
+            None => quote!(::core::option::Option::None),
+        };
+        match option_inner(&field.ty) {
+            Some(inner) => {
+                reads.push(quote!(let #ident = loader.optional::<#inner>(#var, #default);));
+                inits.push(quote!(#ident));
+            }
+            None => {
+                let ty = &field.ty;
+                reads.push(quote!(let #ident = loader.required::<#ty>(#var, #default);));
+                inits.push(quote!(#ident: #ident.unwrap()));
+            }
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rtlambda::config::LambdaConfig for #name #ty_generics #where_clause {
+            fn from_vars<F: Fn(&str) -> ::core::option::Option<::std::string::String>>(
+                get_var: F,
+            ) -> ::core::result::Result<Self, ::rtlambda::config::ConfigError> {
+                let mut loader = ::rtlambda::config::ConfigLoader::new(get_var);
+                #(#reads)*
+                loader.finish()?;
+                // Required fields are set unless the loader returned an error.
+                ::core::result::Result::Ok(Self { #(#inits),* })
+            }
+        }
+    })
+}
+
+/// Returns `T` if `ty` is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_to_string(input: DeriveInput) -> String {
+        expand(&input).unwrap().to_string()
+    }
+
+    fn expand_error(input: DeriveInput) -> String {
+        expand(&input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn reads_required_and_optional_fields() {
+        let expanded = expand_to_string(syn::parse_quote! {
+            struct Config {
+                table: String,
+                endpoint: Option<String>,
+            }
+        });
+        assert!(expanded.contains(
+            &quote!(let table = loader.required::<String>("TABLE", ::core::option::Option::None);)
+                .to_string()
+        ));
+        assert!(expanded.contains(
+            &quote!(let endpoint = loader.optional::<String>("ENDPOINT", ::core::option::Option::None);)
+                .to_string()
+        ));
+        assert!(expanded.contains(
+            &quote!(Self {
+                table: table.unwrap(),
+                endpoint
+            })
+            .to_string()
+        ));
+    }
+
+    #[test]
+    fn applies_the_prefix_env_and_default_attributes() {
+        let expanded = expand_to_string(syn::parse_quote! {
+            #[lambda_config(prefix = "APP_")]
+            struct Config {
+                #[lambda_config(env = "BATCH", default = "10")]
+                batch_size: usize,
+                retries: Option<u8>,
+            }
+        });
+        assert!(expanded.contains(
+            &quote!(let batch_size = loader.required::<usize>("APP_BATCH", ::core::option::Option::Some("10"));)
+                .to_string()
+        ));
+        assert!(expanded.contains(
+            &quote!(let retries = loader.optional::<u8>("APP_RETRIES", ::core::option::Option::None);)
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_inputs() {
+        assert_eq!(
+            expand_error(syn::parse_quote!(
+                struct Config(String);
+            )),
+            "LambdaConfig can only be derived for structs with named fields"
+        );
+        assert_eq!(
+            expand_error(syn::parse_quote!(
+                enum Config {
+                    A,
+                }
+            )),
+            "LambdaConfig can only be derived for structs"
+        );
+        assert_eq!(
+            expand_error(syn::parse_quote! {
+                #[lambda_config(env = "CONFIG")]
+                struct Config {
+                    table: String,
+                }
+            }),
+            "unsupported attribute, expected one of: prefix"
+        );
+        assert_eq!(
+            expand_error(syn::parse_quote! {
+                struct Config {
+                    #[lambda_config(prefix = "APP_")]
+                    table: String,
+                }
+            }),
+            "unsupported attribute, expected one of: env, default"
+        );
+    }
+
+    #[test]
+    fn detects_option_fields() {
+        let inner = |ty: Type| option_inner(&ty).map(|inner| quote!(#inner).to_string());
+        assert_eq!(
+            inner(syn::parse_quote!(Option<u16>)),
+            Some("u16".to_string())
+        );
+        assert_eq!(
+            inner(syn::parse_quote!(std::option::Option<String>)),
+            Some("String".to_string())
+        );
+        assert_eq!(inner(syn::parse_quote!(u16)), None);
+        assert_eq!(inner(syn::parse_quote!(Vec<u16>)), None);
+    }
+}
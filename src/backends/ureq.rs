@@ -20,7 +20,7 @@ macro_rules! copy_str_header {
 pub struct UreqResponse {
     body: Option<String>,
     status: u16,
-    _request_id: String,
+    _request_id: Option<String>,
     _deadline: Option<Duration>,
     _arn: Option<String>,
     _trace_id: Option<String>,
@@ -34,15 +34,8 @@ impl UreqResponse {
         // Copy status
         let status = resp.status();
 
-        // Copy AWS headers
-        let _request_id = match resp.header(AWS_REQ_ID) {
-            Some(v) => v.to_string(),
-            None => {
-                return Err(Error::new(
-                    "Missing Lambda-Runtime-Aws-Request-Id header".to_string(),
-                ))
-            }
-        };
+        // Copy AWS headers - only responses to next invocation requests carry a request id
+        let _request_id = copy_str_header!(resp, AWS_REQ_ID);
 
         // Parse milliseconds to Duration
         let _deadline = match resp.header(AWS_DEADLINE_MS) {
@@ -89,7 +82,7 @@ impl LambdaAPIResponse for UreqResponse {
 
     #[inline]
     fn aws_request_id(&self) -> Option<&str> {
-        Some(&self._request_id)
+        self._request_id.as_deref()
     }
     #[inline]
     fn deadline(&self) -> Option<Duration> {
@@ -0,0 +1,247 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::error::ErrorRequest;
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[cfg(feature = "derive")]
+pub use rtlambda_derive::LambdaConfig;
+
+/// The error type reported when loading a [`LambdaConfig`] fails during initialization.
+pub const CONFIG_ERR_TYPE: &str = "Runtime.ConfigError";
+
+/// A configuration struct loaded from env-vars, usually implemented using `#[derive(LambdaConfig)]` with the `derive` feature.
+///
+/// The derive macro reads each field from the env-var named after the field in upper case, parsed using [`FromStr`].
+/// Fields of type `Option<T>` are optional, and other fields are required unless they have a default value.
+/// The following attributes are supported:
+/// - `#[lambda_config(prefix = "APP_")]` on the struct - prepended to the names of all env-vars.
+/// - `#[lambda_config(env = "NAME")]` on a field - the name of the env-var, without the prefix.
+/// - `#[lambda_config(default = "value")]` on a field - parsed when the env-var is not set.
+///
+/// All the errors are collected into a single [`ConfigError`].
+///
+#[cfg_attr(feature = "derive", doc = "```")]
+#[cfg_attr(not(feature = "derive"), doc = "```ignore")]
+/// use rtlambda::config::LambdaConfig;
+///
+/// #[derive(LambdaConfig)]
+/// #[lambda_config(prefix = "APP_")]
+/// struct Config {
+///     table: String,
+///     #[lambda_config(env = "BATCH", default = "10")]
+///     batch_size: usize,
+///     endpoint: Option<String>,
+/// }
+///
+/// let config = Config::from_vars(|name| (name == "APP_TABLE").then(|| "orders".to_string())).unwrap();
+/// assert_eq!(config.table, "orders");
+/// assert_eq!(config.batch_size, 10);
+/// assert_eq!(config.endpoint, None);
+///
+/// let err = Config::from_vars(|name| (name == "APP_BATCH").then(|| "ten".to_string())).err().unwrap();
+/// assert_eq!(
+///     err.to_string(),
+///     "Failed loading configuration. APP_TABLE: not set. APP_BATCH: invalid value, invalid digit found in string."
+/// );
+/// ```
+pub trait LambdaConfig: Sized {
+    /// Loads the configuration using `get_var` to read the value of each env-var.
+    fn from_vars<F: Fn(&str) -> Option<String>>(get_var: F) -> Result<Self, ConfigError>;
+
+    /// Loads the configuration from the env-vars of the process.
+    fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+}
+
+/// An error reading or parsing a single env-var of a [`LambdaConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// The name of the env-var.
+    pub var: String,
+    pub message: String,
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.var, self.message)
+    }
+}
+
+/// The errors that occurred while loading a [`LambdaConfig`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigError {
+    pub errors: Vec<FieldError>,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed loading configuration.")?;
+        for error in &self.errors {
+            write!(f, " {}.", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for ErrorRequest {
+    fn from(err: ConfigError) -> Self {
+        ErrorRequest::new(CONFIG_ERR_TYPE, &err.to_string())
+    }
+}
+
+/// Reads and parses the fields of a [`LambdaConfig`], collecting all the errors. Used by the code generated by the derive macro.
+pub struct ConfigLoader<F: Fn(&str) -> Option<String>> {
+    get_var: F,
+    errors: Vec<FieldError>,
+}
+
+impl<F: Fn(&str) -> Option<String>> ConfigLoader<F> {
+    pub fn new(get_var: F) -> Self {
+        Self {
+            get_var,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Reads a required env-var, falling back to parsing `default` if it is not set.
+    /// Returns `None` and records an error if the env-var is missing or can't be parsed.
+    pub fn required<T>(&mut self, var: &str, default: Option<&str>) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = (self.get_var)(var);
+        match value.as_deref().or(default) {
+            Some(value) => self.parse(var, value),
+            None => {
+                self.push_error(var, "not set".to_string());
+                None
+            }
+        }
+    }
+
+    /// Reads an optional env-var, falling back to parsing `default` if it is not set.
+    /// Records an error if the value can't be parsed.
+    pub fn optional<T>(&mut self, var: &str, default: Option<&str>) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = (self.get_var)(var);
+        self.parse(var, value.as_deref().or(default)?)
+    }
+
+    /// Returns the collected errors, if any.
+    pub fn finish(self) -> Result<(), ConfigError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError {
+                errors: self.errors,
+            })
+        }
+    }
+
+    fn parse<T>(&mut self, var: &str, value: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match value.parse::<T>() {
+            Ok(parsed) => Some(parsed),
+            Err(err) => {
+                // The value itself is left out of the error, since env-vars often hold secrets.
+                self.push_error(var, format!("invalid value, {}", err));
+                None
+            }
+        }
+    }
+
+    fn push_error(&mut self, var: &str, message: String) {
+        self.errors.push(FieldError {
+            var: var.to_string(),
+            message,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn loader(vars: &[(&str, &str)]) -> ConfigLoader<impl Fn(&str) -> Option<String>> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        ConfigLoader::new(move |name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn reads_required_fields() {
+        let mut loader = loader(&[("PORT", "8080")]);
+        assert_eq!(loader.required::<u16>("PORT", None), Some(8080));
+        assert_eq!(loader.required::<u16>("RETRIES", Some("3")), Some(3));
+        assert!(loader.finish().is_ok());
+
+        let mut loader = self::loader(&[]);
+        assert_eq!(loader.required::<u16>("PORT", None), None);
+        assert_eq!(
+            loader.finish().unwrap_err().errors,
+            vec![FieldError {
+                var: "PORT".to_string(),
+                message: "not set".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_optional_fields() {
+        let mut loader = loader(&[("PORT", "8080")]);
+        assert_eq!(loader.optional::<u16>("PORT", None), Some(8080));
+        assert_eq!(loader.optional::<u16>("RETRIES", None), None);
+        assert_eq!(loader.optional::<u16>("TIMEOUT", Some("30")), Some(30));
+        assert!(loader.finish().is_ok());
+    }
+
+    #[test]
+    fn prefers_the_env_var_to_the_default() {
+        let mut loader = loader(&[("PORT", "8080")]);
+        assert_eq!(loader.required::<u16>("PORT", Some("80")), Some(8080));
+        assert_eq!(loader.optional::<u16>("PORT", Some("80")), Some(8080));
+    }
+
+    #[test]
+    fn collects_all_errors_without_the_values() {
+        let mut loader = loader(&[("PORT", "http"), ("DEBUG", "secret-token")]);
+        assert_eq!(loader.required::<u16>("PORT", None), None);
+        assert_eq!(loader.required::<String>("TABLE", None), None);
+        assert_eq!(loader.optional::<bool>("DEBUG", None), None);
+        // An invalid default is reported like an invalid value.
+        assert_eq!(loader.required::<u64>("RETRIES", Some("-1")), None);
+
+        let err = loader.finish().unwrap_err();
+        assert_eq!(err.errors.len(), 4);
+        assert_eq!(
+            err.to_string(),
+            "Failed loading configuration. \
+             PORT: invalid value, invalid digit found in string. \
+             TABLE: not set. \
+             DEBUG: invalid value, provided string was not `true` or `false`. \
+             RETRIES: invalid value, invalid digit found in string."
+        );
+        assert!(!err.to_string().contains("secret-token"));
+
+        let request = ErrorRequest::from(err);
+        assert_eq!(request.error_type, CONFIG_ERR_TYPE);
+    }
+}
@@ -0,0 +1,670 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::data::env::{LambdaRuntimeEnv, RuntimeEnvVars};
+use crate::data::response::LambdaAPIResponse;
+use crate::data::time::days_from_civil;
+use crate::error::Error;
+use crate::runtime::lock;
+use crate::transport::Transport;
+
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+pub static ACCESS_KEY_ID: &str = "AWS_ACCESS_KEY_ID";
+pub static SECRET_ACCESS_KEY: &str = "AWS_SECRET_ACCESS_KEY";
+pub static SESSION_TOKEN: &str = "AWS_SESSION_TOKEN";
+pub static CONTAINER_CREDENTIALS_FULL_URI: &str = "AWS_CONTAINER_CREDENTIALS_FULL_URI";
+/// The env-var holding the path of the container credentials endpoint on [`CONTAINER_CREDENTIALS_HOST`], used instead of
+/// [`CONTAINER_CREDENTIALS_FULL_URI`] if set.
+pub static CONTAINER_CREDENTIALS_RELATIVE_URI: &str = "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI";
+/// The address of the container credentials endpoint when its URI is set in [`CONTAINER_CREDENTIALS_RELATIVE_URI`].
+pub static CONTAINER_CREDENTIALS_HOST: &str = "http://169.254.170.2";
+pub static CONTAINER_AUTHORIZATION_TOKEN: &str = "AWS_CONTAINER_AUTHORIZATION_TOKEN";
+/// The env-var holding the path of a file containing the authorization token, used instead of [`CONTAINER_AUTHORIZATION_TOKEN`] if set.
+pub static CONTAINER_AUTHORIZATION_TOKEN_FILE: &str = "AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE";
+
+/// The default time before their expiry in which cached credentials are refreshed.
+pub const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// Incremented whenever all the cached credentials should be refreshed, e.g. after restoring a SnapStart snapshot.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// A set of AWS credentials.
+///
+/// The secret access key and session token are redacted from the `Debug` output,
+/// and the fields of each instance are overwritten with zeros when it is dropped.
+/// This only limits how long the secrets stay in memory, rather than guaranteeing they are erased:
+/// [`CachingProvider`] hands out clones of the cached credentials, the credentials env-vars stay in the process environment
+/// and in [`crate::data::env::LambdaRuntimeEnv`], and the HTTP client may have copied the values it received.
+#[derive(Clone)]
+pub struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    expiry: Option<SystemTime>,
+}
+
+impl Credentials {
+    pub fn new(
+        access_key_id: String,
+        secret_access_key: String,
+        session_token: Option<String>,
+        expiry: Option<SystemTime>,
+    ) -> Self {
+        Self {
+            access_key_id,
+            secret_access_key,
+            session_token,
+            expiry,
+        }
+    }
+
+    /// Returns the credentials set in the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` env-vars
+    /// when `env_vars` was created, or `None` if the access key id or the secret access key is not set.
+    pub fn from_env_vars<E: RuntimeEnvVars>(env_vars: &E) -> Option<Self> {
+        Some(Self::new(
+            env_vars.get_access_key_id()?.to_string(),
+            env_vars.get_secret_access_key()?.to_string(),
+            env_vars.get_session_token().map(|token| token.to_string()),
+            None,
+        ))
+    }
+
+    #[inline(always)]
+    pub fn access_key_id(&self) -> &str {
+        &self.access_key_id
+    }
+
+    #[inline(always)]
+    pub fn secret_access_key(&self) -> &str {
+        &self.secret_access_key
+    }
+
+    #[inline(always)]
+    pub fn session_token(&self) -> Option<&str> {
+        self.session_token.as_deref()
+    }
+
+    /// Returns the time the credentials expire at, or `None` if it is unknown.
+    #[inline(always)]
+    pub fn expiry(&self) -> Option<SystemTime> {
+        self.expiry
+    }
+
+    /// Returns whether the credentials expire within `margin` from now. Credentials with an unknown expiry never expire.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        match self.expiry {
+            Some(expiry) => expiry <= SystemTime::now() + margin,
+            None => false,
+        }
+    }
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"** redacted **")
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "** redacted **"),
+            )
+            .field("expiry", &self.expiry)
+            .finish()
+    }
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        zeroize(&mut self.access_key_id);
+        zeroize(&mut self.secret_access_key);
+        if let Some(token) = self.session_token.as_mut() {
+            zeroize(token);
+        }
+    }
+}
+
+/// Overwrites the contents of `secret` with zeros, in a way that is not optimized away.
+fn zeroize(secret: &mut String) {
+    // Safety: a string of zeros is valid UTF-8.
+    let bytes = unsafe { secret.as_mut_vec() };
+    for byte in bytes.iter_mut() {
+        // Safety: `byte` is a valid and aligned reference.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// An interface trait for providers of [`Credentials`].
+pub trait CredentialsProvider {
+    /// Returns the current credentials, fetching them if needed.
+    fn credentials(&self) -> Result<Credentials, Error>;
+}
+
+/// Reads the credentials from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` env-vars,
+/// which AWS Lambda sets to the credentials of the function's execution role.
+///
+/// The env-vars are read on each call, unlike [`Credentials::from_env_vars`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvProvider;
+
+impl CredentialsProvider for EnvProvider {
+    fn credentials(&self) -> Result<Credentials, Error> {
+        let var = |name: &str| std::env::var(name).ok();
+        match (var(ACCESS_KEY_ID), var(SECRET_ACCESS_KEY)) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                var(SESSION_TOKEN),
+                None,
+            )),
+            _ => Err(Error::new(format!(
+                "Failed reading credentials from env-vars. {} and {} must be set",
+                ACCESS_KEY_ID, SECRET_ACCESS_KEY
+            ))),
+        }
+    }
+}
+
+/// Fetches the credentials from the container credentials endpoint using any [`Transport`] implementation.
+///
+/// The endpoint is at the path set in the `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` env-var on [`CONTAINER_CREDENTIALS_HOST`],
+/// or at the URI set in the `AWS_CONTAINER_CREDENTIALS_FULL_URI` env-var.
+///
+/// The request is authorized using the token set in the `AWS_CONTAINER_AUTHORIZATION_TOKEN` env-var,
+/// or read on each call from the file set in the `AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE` env-var.
+/// The env-vars are read when the provider is created, unless the URI and token are set explicitly.
+pub struct ContainerProvider<R: LambdaAPIResponse, T: Transport<R>> {
+    transport: T,
+    uri: Option<String>,
+    authorization_token: Option<String>,
+    authorization_token_file: Option<String>,
+    _phantom: PhantomData<fn() -> R>,
+}
+
+impl<R: LambdaAPIResponse, T: Transport<R>> ContainerProvider<R, T> {
+    /// Creates a provider reading the endpoint and token from the env-vars of the process.
+    pub fn new(transport: T) -> Self {
+        Self::from_env_vars(transport, &LambdaRuntimeEnv::from_env())
+    }
+
+    /// Creates a provider reading the endpoint and token from `env_vars`,
+    /// e.g. a [`LambdaRuntimeEnv`] built for running locally.
+    pub fn from_env_vars<E: RuntimeEnvVars>(transport: T, env_vars: &E) -> Self {
+        let var = |name: &str| env_vars.get_other(name).map(|value| value.to_string());
+        let uri = match var(CONTAINER_CREDENTIALS_RELATIVE_URI) {
+            Some(relative_uri) => Some(format!("{}{}", CONTAINER_CREDENTIALS_HOST, relative_uri)),
+            None => var(CONTAINER_CREDENTIALS_FULL_URI),
+        };
+        let authorization_token_file = var(CONTAINER_AUTHORIZATION_TOKEN_FILE);
+        let authorization_token = match authorization_token_file {
+            Some(_) => None,
+            None => var(CONTAINER_AUTHORIZATION_TOKEN),
+        };
+        Self {
+            transport,
+            uri,
+            authorization_token,
+            authorization_token_file,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Sets the URI of the endpoint instead of reading it from the env-vars.
+    pub fn with_full_uri(mut self, full_uri: &str) -> Self {
+        self.uri = Some(full_uri.to_string());
+        self
+    }
+
+    /// Sets the authorization token instead of reading it from the env-vars.
+    pub fn with_authorization_token(mut self, token: &str) -> Self {
+        self.authorization_token = Some(token.to_string());
+        self.authorization_token_file = None;
+        self
+    }
+
+    /// Returns the URI of the endpoint, or `None` if it is not set.
+    #[inline]
+    pub fn uri(&self) -> Option<&str> {
+        self.uri.as_deref()
+    }
+
+    fn authorization_token(&self) -> Result<Option<String>, Error> {
+        match &self.authorization_token_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|token| Some(token.trim().to_string()))
+                .map_err(|err| {
+                    Error::new(format!(
+                        "Failed reading the authorization token from `{}`. {}",
+                        path, err
+                    ))
+                }),
+            None => Ok(self.authorization_token.clone()),
+        }
+    }
+}
+
+impl<R: LambdaAPIResponse, T: Transport<R>> CredentialsProvider for ContainerProvider<R, T> {
+    fn credentials(&self) -> Result<Credentials, Error> {
+        let uri = self.uri().ok_or_else(|| {
+            Error::new(format!(
+                "Failed fetching container credentials. Neither {} nor {} is set",
+                CONTAINER_CREDENTIALS_RELATIVE_URI, CONTAINER_CREDENTIALS_FULL_URI
+            ))
+        })?;
+        let mut token = self.authorization_token()?;
+        let headers = token
+            .as_deref()
+            .map(|token| (vec!["Authorization"], vec![token]));
+        let resp = self.transport.get(uri, None, headers);
+        if let Some(token) = token.as_mut() {
+            zeroize(token);
+        }
+        let resp = resp
+            .map_err(|err| Error::new(format!("Failed fetching container credentials. {}", err)))?;
+        if !resp.is_success() {
+            return Err(Error::new(format!(
+                "Failed fetching container credentials. Status code {}",
+                resp.get_status_code()
+            )));
+        }
+        let mut parsed = serde_json::from_str::<Value>(resp.get_body().unwrap_or(""))
+            .map_err(|err| Error::new(format!("Failed parsing container credentials. {}", err)))?;
+        // Moves the strings out of the parsed JSON instead of copying the secrets.
+        let mut take = |field: &str| match parsed.get_mut(field).map(Value::take) {
+            Some(Value::String(value)) => Some(value),
+            _ => None,
+        };
+        let (access_key_id, secret_access_key, token, expiration) = (
+            take("AccessKeyId"),
+            take("SecretAccessKey"),
+            take("Token"),
+            take("Expiration"),
+        );
+        let expiry = match &expiration {
+            Some(expiration) => Some(parse_timestamp(expiration).ok_or_else(|| {
+                Error::new(format!(
+                    "Failed parsing container credentials. Invalid expiration `{}`",
+                    expiration
+                ))
+            })?),
+            None => None,
+        };
+        match (access_key_id, secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                token,
+                expiry,
+            )),
+            _ => Err(Error::new(
+                "Failed parsing container credentials. AccessKeyId and SecretAccessKey are required"
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+/// Provides the credentials of the function's execution role in AWS Lambda -
+/// from the container credentials endpoint if its URI is set, and from the env-vars otherwise.
+pub struct LambdaProvider<R: LambdaAPIResponse, T: Transport<R>> {
+    container: ContainerProvider<R, T>,
+}
+
+impl<R: LambdaAPIResponse, T: Transport<R>> LambdaProvider<R, T> {
+    /// Creates a provider fetching container credentials using `transport`, see [`ContainerProvider::new`].
+    pub fn new(transport: T) -> Self {
+        Self {
+            container: ContainerProvider::new(transport),
+        }
+    }
+
+    /// Creates a provider fetching container credentials using `transport`, see [`ContainerProvider::from_env_vars`].
+    pub fn from_env_vars<E: RuntimeEnvVars>(transport: T, env_vars: &E) -> Self {
+        Self {
+            container: ContainerProvider::from_env_vars(transport, env_vars),
+        }
+    }
+}
+
+impl<R: LambdaAPIResponse, T: Transport<R>> CredentialsProvider for LambdaProvider<R, T> {
+    fn credentials(&self) -> Result<Credentials, Error> {
+        match self.container.uri() {
+            Some(_) => self.container.credentials(),
+            None => EnvProvider.credentials(),
+        }
+    }
+}
+
+/// Caches the credentials returned by another provider, refreshing them when they are about to expire.
+///
+/// The cached credentials are also refreshed after [`CachingProvider::invalidate`] is called,
+/// and after the runtime restores the execution environment from a SnapStart snapshot.
+pub struct CachingProvider<P: CredentialsProvider> {
+    provider: P,
+    refresh_margin: Duration,
+    cached: Mutex<Option<(Credentials, u64)>>,
+}
+
+impl<P: CredentialsProvider> CachingProvider<P> {
+    /// Creates a provider caching the credentials of `provider`, refreshing them [`DEFAULT_REFRESH_MARGIN`] before they expire.
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Sets the time before their expiry in which the credentials are refreshed.
+    pub fn with_refresh_margin(mut self, margin: Duration) -> Self {
+        self.refresh_margin = margin;
+        self
+    }
+
+    /// Discards the cached credentials, so they are fetched again on the next call.
+    pub fn invalidate(&self) {
+        *lock(&self.cached) = None;
+    }
+}
+
+impl<P: CredentialsProvider> CredentialsProvider for CachingProvider<P> {
+    fn credentials(&self) -> Result<Credentials, Error> {
+        let mut cached = lock(&self.cached);
+        let generation = GENERATION.load(Ordering::Acquire);
+        if let Some((credentials, cached_generation)) = cached.as_ref() {
+            if *cached_generation == generation && !credentials.expires_within(self.refresh_margin)
+            {
+                return Ok(credentials.clone());
+            }
+        }
+        let credentials = self.provider.credentials()?;
+        *cached = Some((credentials.clone(), generation));
+        Ok(credentials)
+    }
+}
+
+/// Creates a [`CachingProvider`] of the function's credentials, fetching container credentials using the ureq transport.
+#[cfg(feature = "ureq")]
+pub fn default_provider() -> CachingProvider<
+    LambdaProvider<crate::backends::ureq::UreqResponse, crate::backends::ureq::UreqTransport>,
+> {
+    CachingProvider::new(LambdaProvider::new(Default::default()))
+}
+
+/// Makes all the [`CachingProvider`]s refresh their credentials on the next call.
+pub(crate) fn invalidate_cached() {
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Parses an RFC 3339 UTC timestamp, e.g. `2022-05-22T10:01:02Z`, optionally with fractional seconds.
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let (date, time) = timestamp.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 {
+        return None;
+    }
+    let nanos = match fraction {
+        "" => 0,
+        fraction => format!("{:0<9}", fraction).get(..9)?.parse::<u32>().ok()?,
+    };
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+
+    let secs = days * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+#[cfg(all(test, feature = "ureq"))]
+mod tests {
+    use super::*;
+    use crate::backends::ureq::{UreqResponse, UreqTransport};
+
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread::JoinHandle;
+
+    static EXPIRATION: &str = "2022-05-22T10:01:02Z";
+    /// [`EXPIRATION`] in seconds since the epoch.
+    const EXPIRATION_SECS: u64 = 1_653_213_662;
+
+    /// Serves a single request on a local stand-in of the container credentials endpoint,
+    /// returning its URI and a handle resolving to the request's lines.
+    fn serve(status: &str, body: &str) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}/v2/credentials", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = BufReader::new(stream.try_clone().unwrap())
+                .lines()
+                .map(Result::unwrap)
+                .take_while(|line| !line.is_empty())
+                .collect();
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (uri, handle)
+    }
+
+    fn credentials_body() -> String {
+        json!({
+            "AccessKeyId": "AKIDEXAMPLE",
+            "SecretAccessKey": "secret-access-key",
+            "Token": "session-token",
+            "Expiration": EXPIRATION,
+        })
+        .to_string()
+    }
+
+    fn authorization(request: &[String]) -> Option<&str> {
+        request.iter().find_map(|line| {
+            let (name, value) = line.split_once(": ")?;
+            name.eq_ignore_ascii_case("authorization").then_some(value)
+        })
+    }
+
+    fn provider_from(env_vars: &[(&str, &str)]) -> ContainerProvider<UreqResponse, UreqTransport> {
+        let env_vars = env_vars
+            .iter()
+            .fold(LambdaRuntimeEnv::builder(), |builder, (name, value)| {
+                builder.with_var(name, value)
+            })
+            .build();
+        ContainerProvider::from_env_vars(UreqTransport::default(), &env_vars)
+    }
+
+    fn provider() -> ContainerProvider<UreqResponse, UreqTransport> {
+        provider_from(&[])
+    }
+
+    #[test]
+    fn fetches_container_credentials() {
+        let (uri, server) = serve("200 OK", &credentials_body());
+        let credentials = provider()
+            .with_full_uri(&uri)
+            .with_authorization_token("explicit-token")
+            .credentials()
+            .unwrap();
+        let request = server.join().unwrap();
+
+        assert_eq!(request[0], "GET /v2/credentials HTTP/1.1");
+        assert_eq!(authorization(&request), Some("explicit-token"));
+        assert_eq!(credentials.access_key_id(), "AKIDEXAMPLE");
+        assert_eq!(credentials.secret_access_key(), "secret-access-key");
+        assert_eq!(credentials.session_token(), Some("session-token"));
+        assert_eq!(
+            credentials.expiry(),
+            Some(UNIX_EPOCH + Duration::from_secs(EXPIRATION_SECS))
+        );
+    }
+
+    #[test]
+    fn fails_on_error_responses() {
+        let (uri, server) = serve("500 Internal Server Error", "{}");
+        assert!(provider().with_full_uri(&uri).credentials().is_err());
+        server.join().unwrap();
+
+        let (uri, server) = serve("200 OK", &json!({"AccessKeyId": "AKIDEXAMPLE"}).to_string());
+        assert!(provider().with_full_uri(&uri).credentials().is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reads_the_endpoint_and_token_from_env_vars() {
+        let (uri, server) = serve("200 OK", &credentials_body());
+        let credentials = provider_from(&[
+            (CONTAINER_CREDENTIALS_FULL_URI, &uri),
+            (CONTAINER_AUTHORIZATION_TOKEN, "env-token"),
+        ])
+        .credentials()
+        .unwrap();
+        assert_eq!(authorization(&server.join().unwrap()), Some("env-token"));
+        assert_eq!(credentials.access_key_id(), "AKIDEXAMPLE");
+
+        // The explicit token takes precedence over the env-vars.
+        let (uri, server) = serve("200 OK", &credentials_body());
+        provider_from(&[(CONTAINER_AUTHORIZATION_TOKEN, "env-token")])
+            .with_full_uri(&uri)
+            .with_authorization_token("explicit-token")
+            .credentials()
+            .unwrap();
+        assert_eq!(
+            authorization(&server.join().unwrap()),
+            Some("explicit-token")
+        );
+
+        // The relative URI takes precedence over the full URI.
+        let provider = provider_from(&[
+            (CONTAINER_CREDENTIALS_RELATIVE_URI, "/v2/credentials/id"),
+            (CONTAINER_CREDENTIALS_FULL_URI, &uri),
+        ]);
+        assert_eq!(
+            provider.uri(),
+            Some("http://169.254.170.2/v2/credentials/id")
+        );
+        assert_eq!(provider.with_full_uri(&uri).uri(), Some(uri.as_str()));
+
+        assert!(provider_from(&[]).uri().is_none());
+        assert!(provider_from(&[]).credentials().is_err());
+    }
+
+    #[test]
+    fn reads_the_token_file_on_each_call() {
+        let token_file =
+            std::env::temp_dir().join(format!("rtlambda-token-{}", std::process::id()));
+        std::fs::write(&token_file, "file-token\n").unwrap();
+        let (uri, server) = serve("200 OK", &credentials_body());
+        // The token file takes precedence over the token env-var.
+        let provider = provider_from(&[
+            (CONTAINER_CREDENTIALS_FULL_URI, &uri),
+            (CONTAINER_AUTHORIZATION_TOKEN, "env-token"),
+            (
+                CONTAINER_AUTHORIZATION_TOKEN_FILE,
+                token_file.to_str().unwrap(),
+            ),
+        ]);
+        provider.credentials().unwrap();
+        assert_eq!(authorization(&server.join().unwrap()), Some("file-token"));
+
+        std::fs::remove_file(&token_file).unwrap();
+        let err = provider.credentials().unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Failed reading the authorization token"));
+    }
+
+    #[test]
+    fn parses_expiration_timestamps() {
+        let expiry = UNIX_EPOCH + Duration::from_secs(EXPIRATION_SECS);
+        assert_eq!(parse_timestamp(EXPIRATION), Some(expiry));
+        assert_eq!(
+            parse_timestamp("2022-05-22T10:01:02.25Z"),
+            Some(expiry + Duration::from_millis(250))
+        );
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        for invalid in [
+            "2022-05-22T10:01:02",
+            "2022-05-22 10:01:02Z",
+            "2022-13-22T10:01:02Z",
+            "2022-05-22T24:01:02Z",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert_eq!(parse_timestamp(invalid), None, "{}", invalid);
+        }
+    }
+
+    /// Returns new credentials on each call, expiring `lifetime` from now.
+    struct CountingProvider {
+        calls: AtomicUsize,
+        lifetime: Duration,
+    }
+
+    impl CountingProvider {
+        fn new(lifetime: Duration) -> Self {
+            Self {
+                calls: AtomicUsize::new(0),
+                lifetime,
+            }
+        }
+    }
+
+    impl CredentialsProvider for CountingProvider {
+        fn credentials(&self) -> Result<Credentials, Error> {
+            let call = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+            Ok(Credentials::new(
+                format!("AKID{}", call),
+                "secret".to_string(),
+                None,
+                Some(SystemTime::now() + self.lifetime),
+            ))
+        }
+    }
+
+    fn access_key_id<P: CredentialsProvider>(provider: &P) -> String {
+        provider.credentials().unwrap().access_key_id().to_string()
+    }
+
+    #[test]
+    fn refreshes_cached_credentials() {
+        let provider = CachingProvider::new(CountingProvider::new(Duration::from_secs(3_600)));
+        assert_eq!(access_key_id(&provider), "AKID1");
+        assert_eq!(access_key_id(&provider), "AKID1");
+
+        // E.g. after restoring a SnapStart snapshot.
+        invalidate_cached();
+        assert_eq!(access_key_id(&provider), "AKID2");
+        assert_eq!(access_key_id(&provider), "AKID2");
+
+        provider.invalidate();
+        assert_eq!(access_key_id(&provider), "AKID3");
+
+        // Credentials expiring within the refresh margin are refreshed on each call.
+        let provider = CachingProvider::new(CountingProvider::new(Duration::from_secs(60)));
+        assert_eq!(access_key_id(&provider), "AKID1");
+        assert_eq!(access_key_id(&provider), "AKID2");
+
+        let provider = CachingProvider::new(CountingProvider::new(Duration::from_secs(60)))
+            .with_refresh_margin(Duration::from_secs(30));
+        assert_eq!(access_key_id(&provider), "AKID1");
+        assert_eq!(access_key_id(&provider), "AKID1");
+    }
+}
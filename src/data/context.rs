@@ -2,9 +2,15 @@
 
 // `SPDX-License-Identifier: MIT OR Apache-2.0`
 
-use crate::data::env::RuntimeEnvVars;
+use crate::data::deadline::DeadlineToken;
+use crate::data::env::{InitializationType, RuntimeEnvVars};
+use crate::data::identity::{ClientContext, CognitoIdentity};
 use crate::data::response::LambdaAPIResponse;
+use crate::data::trace::TraceHeader;
 use crate::error::Error;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
 /// An interface trait that should be implemented by types representing a [Context object]([https://docs.aws.amazon.com/lambda/latest/dg/python-context.html]).
@@ -26,20 +32,98 @@ pub trait LambdaContext {
             Err(e) => Err(Error::new(e.to_string())),
         }
     }
+    /// Returns the safety margin subtracted from the deadline by [`LambdaContext::deadline_token`].
+    fn deadline_margin(&self) -> Duration {
+        Duration::ZERO
+    }
+    /// Returns a [`DeadlineToken`] tracking the execution deadline of the invocation minus [`LambdaContext::deadline_margin`],
+    /// or `None` if the deadline is unknown.
+    fn deadline_token(&self) -> Option<DeadlineToken> {
+        self.get_deadline()
+            .map(|deadline| DeadlineToken::new(deadline, self.deadline_margin()))
+    }
     // Per-invocation data (event-related)
     fn get_deadline(&self) -> Option<Duration>;
     fn invoked_function_arn(&self) -> Option<&str>;
     fn aws_request_id(&self) -> Option<&str>;
+    /// Returns the raw value of the `Lambda-Runtime-Trace-Id` header.
+    ///
+    /// Defaults to `None`, so that context types written before the header was exposed keep compiling.
+    fn raw_trace_id(&self) -> Option<&str> {
+        None
+    }
+    /// Parses the X-Ray trace header of the invocation on demand.
+    /// Returns `Ok(None)` if the header is missing, or an error if it can't be parsed.
+    fn trace_header(&self) -> Result<Option<TraceHeader>, Error> {
+        self.raw_trace_id().map(TraceHeader::parse).transpose()
+    }
     // Per-runtime data (constant accross the lifetime of the runtime, taken from env-vars)
     fn function_name(&self) -> Option<&str>;
     fn function_version(&self) -> Option<&str>;
     fn memory_limit_in_mb(&self) -> Option<usize>;
     fn log_group_name(&self) -> Option<&str>;
     fn log_stream_name(&self) -> Option<&str>;
+    /// Returns the initialization type of the execution environment.
+    ///
+    /// Defaults to [`InitializationType::Unknown`].
+    fn initialization_type(&self) -> InitializationType {
+        InitializationType::Unknown
+    }
+    // Per-environment lifecycle data (tracked by the runtime)
+    /// Returns the index of the invocation among the invocations served by the execution environment, starting from 0.
+    ///
+    /// Defaults to 0, so context types that don't track invocations report every invocation as a cold start.
+    fn invocation_index(&self) -> u64 {
+        0
+    }
+    /// Returns `true` for the first invocation served by the execution environment after initialization.
+    fn is_cold_start(&self) -> bool {
+        self.invocation_index() == 0
+    }
+    /// Returns how long the initialization of the runtime took.
+    ///
+    /// Defaults to zero.
+    fn init_duration(&self) -> Duration {
+        Duration::ZERO
+    }
     // Identity and Client context - see [https://docs.aws.amazon.com/lambda/latest/dg/python-context.html]
-    // TODO - parse these structures and return a relevant type
     fn cognito_identity(&self) -> Option<&str>;
     fn client_context(&self) -> Option<&str>;
+    /// Returns the Cognito identity of the invocation parsed from [`LambdaContext::cognito_identity`],
+    /// `Ok(None)` if it is missing, or an error if it can't be parsed.
+    ///
+    /// The default implementation parses the identity on each call,
+    /// while [`RefLambdaContext`] and [`OwnedLambdaContext`] parse it on the first call and return a reference to the cached result.
+    fn parsed_cognito_identity(&self) -> Result<Option<Cow<'_, CognitoIdentity>>, Error> {
+        let identity = self
+            .cognito_identity()
+            .map(CognitoIdentity::from_json)
+            .transpose()?;
+        Ok(identity.map(Cow::Owned))
+    }
+    /// Returns the client context of the invocation parsed from [`LambdaContext::client_context`],
+    /// `Ok(None)` if it is missing, or an error if it can't be parsed.
+    ///
+    /// The default implementation parses the client context on each call,
+    /// while [`RefLambdaContext`] and [`OwnedLambdaContext`] parse it on the first call and return a reference to the cached result.
+    fn parsed_client_context(&self) -> Result<Option<Cow<'_, ClientContext>>, Error> {
+        let client_context = self
+            .client_context()
+            .map(ClientContext::from_json)
+            .transpose()?;
+        Ok(client_context.map(Cow::Owned))
+    }
+}
+
+/// The result of parsing a JSON header value, cached by the context object.
+type Parsed<T> = Result<Option<T>, Error>;
+
+/// Borrows a cached [`Parsed`] value.
+fn borrow_parsed<T: Clone>(parsed: &Parsed<T>) -> Result<Option<Cow<'_, T>>, Error> {
+    match parsed {
+        Ok(value) => Ok(value.as_ref().map(Cow::Borrowed)),
+        Err(err) => Err(err.clone()),
+    }
 }
 
 /// A generic implementation of [`LambdaContext`] that relies on **borrowing** existing owned
@@ -62,6 +146,69 @@ where
     pub env_vars: &'a E,
     /// A shared reference to a type implementing [`crate::data::response::LambdaAPIResponse`].
     pub invo_resp: &'a R,
+    /// The safety margin used when creating a [`DeadlineToken`].
+    pub deadline_margin: Duration,
+    /// The index of the invocation among the invocations served by the runtime.
+    pub invocation_index: u64,
+    /// How long the initialization of the runtime took.
+    pub init_duration: Duration,
+    parsed_cognito_identity: OnceCell<Parsed<CognitoIdentity>>,
+    parsed_client_context: OnceCell<Parsed<ClientContext>>,
+}
+
+impl<'a, E, R> RefLambdaContext<'a, E, R>
+where
+    E: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+{
+    /// Constructs a new [`RefLambdaContext`] borrowing `env_vars` and `invo_resp`.
+    pub fn new(env_vars: &'a E, invo_resp: &'a R) -> Self {
+        Self {
+            env_vars,
+            invo_resp,
+            deadline_margin: Duration::ZERO,
+            invocation_index: 0,
+            init_duration: Duration::ZERO,
+            parsed_cognito_identity: OnceCell::new(),
+            parsed_client_context: OnceCell::new(),
+        }
+    }
+
+    /// Sets the safety margin used when creating a [`DeadlineToken`].
+    pub fn with_deadline_margin(mut self, margin: Duration) -> Self {
+        self.deadline_margin = margin;
+        self
+    }
+
+    /// Sets the index of the invocation among the invocations served by the runtime.
+    pub fn with_invocation_index(mut self, invocation_index: u64) -> Self {
+        self.invocation_index = invocation_index;
+        self
+    }
+
+    /// Sets how long the initialization of the runtime took.
+    pub fn with_init_duration(mut self, init_duration: Duration) -> Self {
+        self.init_duration = init_duration;
+        self
+    }
+}
+
+impl<'a, E, R> Clone for RefLambdaContext<'a, E, R>
+where
+    E: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+{
+    fn clone(&self) -> Self {
+        Self {
+            env_vars: self.env_vars,
+            invo_resp: self.invo_resp,
+            deadline_margin: self.deadline_margin,
+            invocation_index: self.invocation_index,
+            init_duration: self.init_duration,
+            parsed_cognito_identity: self.parsed_cognito_identity.clone(),
+            parsed_client_context: self.parsed_client_context.clone(),
+        }
+    }
 }
 
 impl<'a, E, R> LambdaContext for RefLambdaContext<'a, E, R>
@@ -74,6 +221,11 @@ where
         self.invo_resp.deadline()
     }
 
+    #[inline(always)]
+    fn deadline_margin(&self) -> Duration {
+        self.deadline_margin
+    }
+
     #[inline(always)]
     fn invoked_function_arn(&self) -> Option<&str> {
         self.invo_resp.invoked_function_arn()
@@ -84,6 +236,11 @@ where
         self.invo_resp.aws_request_id()
     }
 
+    #[inline(always)]
+    fn raw_trace_id(&self) -> Option<&str> {
+        self.invo_resp.trace_id()
+    }
+
     #[inline(always)]
     fn function_name(&self) -> Option<&str> {
         self.env_vars.get_function_name()
@@ -109,6 +266,21 @@ where
         self.env_vars.get_log_stream_name()
     }
 
+    #[inline(always)]
+    fn initialization_type(&self) -> InitializationType {
+        self.env_vars.get_initialization_type()
+    }
+
+    #[inline(always)]
+    fn invocation_index(&self) -> u64 {
+        self.invocation_index
+    }
+
+    #[inline(always)]
+    fn init_duration(&self) -> Duration {
+        self.init_duration
+    }
+
     #[inline(always)]
     fn cognito_identity(&self) -> Option<&str> {
         self.invo_resp.cognito_identity()
@@ -118,4 +290,356 @@ where
     fn client_context(&self) -> Option<&str> {
         self.invo_resp.client_context()
     }
+
+    fn parsed_cognito_identity(&self) -> Result<Option<Cow<'_, CognitoIdentity>>, Error> {
+        borrow_parsed(self.parsed_cognito_identity.get_or_init(|| {
+            self.cognito_identity()
+                .map(CognitoIdentity::from_json)
+                .transpose()
+        }))
+    }
+
+    fn parsed_client_context(&self) -> Result<Option<Cow<'_, ClientContext>>, Error> {
+        borrow_parsed(self.parsed_client_context.get_or_init(|| {
+            self.client_context()
+                .map(ClientContext::from_json)
+                .transpose()
+        }))
+    }
+}
+
+/// The per-runtime subset of [`crate::data::env::RuntimeEnvVars`] exposed by the context object.
+///
+/// Instances are meant to be created once and shared between [`OwnedLambdaContext`] objects through an [`Arc`].
+#[derive(Debug, Clone, Default)]
+pub struct ContextEnv {
+    pub function_name: Option<String>,
+    pub function_version: Option<String>,
+    pub memory_limit_in_mb: Option<usize>,
+    pub log_group_name: Option<String>,
+    pub log_stream_name: Option<String>,
+    pub initialization_type: InitializationType,
+}
+
+impl ContextEnv {
+    /// Constructs a new [`ContextEnv`] by copying the relevant values from a type implementing [`crate::data::env::RuntimeEnvVars`].
+    pub fn from_env_vars<E: RuntimeEnvVars>(env_vars: &E) -> Self {
+        Self {
+            function_name: env_vars.get_function_name().map(|v| v.to_string()),
+            function_version: env_vars.get_function_version().map(|v| v.to_string()),
+            memory_limit_in_mb: env_vars.get_function_memory_size(),
+            log_group_name: env_vars.get_log_group_name().map(|v| v.to_string()),
+            log_stream_name: env_vars.get_log_stream_name().map(|v| v.to_string()),
+            initialization_type: env_vars.get_initialization_type(),
+        }
+    }
+}
+
+/// An implementation of [`LambdaContext`] that **owns** its data and can therefore outlive the invocation it was created for.
+///
+/// Per-runtime data is shared through an [`Arc`] of [`ContextEnv`], while per-invocation data is copied
+/// from the [`crate::data::response::LambdaAPIResponse`] that carried the event.
+#[derive(Debug, Clone)]
+pub struct OwnedLambdaContext {
+    env: Arc<ContextEnv>,
+    aws_request_id: Option<String>,
+    deadline: Option<Duration>,
+    deadline_margin: Duration,
+    invoked_function_arn: Option<String>,
+    trace_id: Option<String>,
+    cognito_identity: Option<String>,
+    client_context: Option<String>,
+    invocation_index: u64,
+    init_duration: Duration,
+    parsed_cognito_identity: OnceLock<Parsed<CognitoIdentity>>,
+    parsed_client_context: OnceLock<Parsed<ClientContext>>,
+}
+
+impl OwnedLambdaContext {
+    /// Constructs a new [`OwnedLambdaContext`] sharing `env` and copying the invocation data from `invo_resp`.
+    pub fn new<R: LambdaAPIResponse>(env: Arc<ContextEnv>, invo_resp: &R) -> Self {
+        Self {
+            env,
+            aws_request_id: invo_resp.aws_request_id().map(|v| v.to_string()),
+            deadline: invo_resp.deadline(),
+            deadline_margin: Duration::ZERO,
+            invoked_function_arn: invo_resp.invoked_function_arn().map(|v| v.to_string()),
+            trace_id: invo_resp.trace_id().map(|v| v.to_string()),
+            cognito_identity: invo_resp.cognito_identity().map(|v| v.to_string()),
+            client_context: invo_resp.client_context().map(|v| v.to_string()),
+            invocation_index: 0,
+            init_duration: Duration::ZERO,
+            parsed_cognito_identity: OnceLock::new(),
+            parsed_client_context: OnceLock::new(),
+        }
+    }
+
+    /// Constructs a new [`OwnedLambdaContext`] from a [`RefLambdaContext`], sharing an existing `env` instead of copying the env-vars.
+    pub fn from_ref<E, R>(context: &RefLambdaContext<E, R>, env: Arc<ContextEnv>) -> Self
+    where
+        E: RuntimeEnvVars,
+        R: LambdaAPIResponse,
+    {
+        Self::new(env, context.invo_resp)
+            .with_deadline_margin(context.deadline_margin)
+            .with_invocation_index(context.invocation_index)
+            .with_init_duration(context.init_duration)
+    }
+
+    /// Sets the safety margin used when creating a [`DeadlineToken`].
+    pub fn with_deadline_margin(mut self, margin: Duration) -> Self {
+        self.deadline_margin = margin;
+        self
+    }
+
+    /// Sets the index of the invocation among the invocations served by the runtime.
+    pub fn with_invocation_index(mut self, invocation_index: u64) -> Self {
+        self.invocation_index = invocation_index;
+        self
+    }
+
+    /// Sets how long the initialization of the runtime took.
+    pub fn with_init_duration(mut self, init_duration: Duration) -> Self {
+        self.init_duration = init_duration;
+        self
+    }
+
+    /// Returns the shared per-runtime data of this context.
+    #[inline(always)]
+    pub fn env(&self) -> &Arc<ContextEnv> {
+        &self.env
+    }
+}
+
+impl<'a, E, R> From<&RefLambdaContext<'a, E, R>> for OwnedLambdaContext
+where
+    E: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+{
+    fn from(context: &RefLambdaContext<'a, E, R>) -> Self {
+        Self::from_ref(
+            context,
+            Arc::new(ContextEnv::from_env_vars(context.env_vars)),
+        )
+    }
+}
+
+// The owned context is meant to be moved across threads and stored, make sure it stays that way.
+const _: fn() = || {
+    fn assert_movable<C: Send + Sync + Clone + 'static>() {}
+    assert_movable::<OwnedLambdaContext>();
+};
+
+impl LambdaContext for OwnedLambdaContext {
+    #[inline(always)]
+    fn get_deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    #[inline(always)]
+    fn deadline_margin(&self) -> Duration {
+        self.deadline_margin
+    }
+
+    #[inline(always)]
+    fn invoked_function_arn(&self) -> Option<&str> {
+        self.invoked_function_arn.as_deref()
+    }
+
+    #[inline(always)]
+    fn aws_request_id(&self) -> Option<&str> {
+        self.aws_request_id.as_deref()
+    }
+
+    #[inline(always)]
+    fn raw_trace_id(&self) -> Option<&str> {
+        self.trace_id.as_deref()
+    }
+
+    #[inline(always)]
+    fn function_name(&self) -> Option<&str> {
+        self.env.function_name.as_deref()
+    }
+
+    #[inline(always)]
+    fn function_version(&self) -> Option<&str> {
+        self.env.function_version.as_deref()
+    }
+
+    #[inline(always)]
+    fn memory_limit_in_mb(&self) -> Option<usize> {
+        self.env.memory_limit_in_mb
+    }
+
+    #[inline(always)]
+    fn log_group_name(&self) -> Option<&str> {
+        self.env.log_group_name.as_deref()
+    }
+
+    #[inline(always)]
+    fn log_stream_name(&self) -> Option<&str> {
+        self.env.log_stream_name.as_deref()
+    }
+
+    #[inline(always)]
+    fn initialization_type(&self) -> InitializationType {
+        self.env.initialization_type
+    }
+
+    #[inline(always)]
+    fn invocation_index(&self) -> u64 {
+        self.invocation_index
+    }
+
+    #[inline(always)]
+    fn init_duration(&self) -> Duration {
+        self.init_duration
+    }
+
+    #[inline(always)]
+    fn cognito_identity(&self) -> Option<&str> {
+        self.cognito_identity.as_deref()
+    }
+
+    #[inline(always)]
+    fn client_context(&self) -> Option<&str> {
+        self.client_context.as_deref()
+    }
+
+    fn parsed_cognito_identity(&self) -> Result<Option<Cow<'_, CognitoIdentity>>, Error> {
+        borrow_parsed(self.parsed_cognito_identity.get_or_init(|| {
+            self.cognito_identity()
+                .map(CognitoIdentity::from_json)
+                .transpose()
+        }))
+    }
+
+    fn parsed_client_context(&self) -> Result<Option<Cow<'_, ClientContext>>, Error> {
+        borrow_parsed(self.parsed_client_context.get_or_init(|| {
+            self.client_context()
+                .map(ClientContext::from_json)
+                .transpose()
+        }))
+    }
+}
+
+/// Selects the type of the context object passed by [`crate::runtime::DefaultRuntime`] to the event handler.
+///
+/// The runtime always creates a [`RefLambdaContext`] for each invocation and converts it
+/// using [`ContextKind::from_ref`], along with the [`ContextEnv`] it shares between invocations.
+pub trait ContextKind<E, R>
+where
+    E: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+{
+    /// The context type passed to the event handler.
+    type Context<'a>: LambdaContext
+    where
+        E: 'a,
+        R: 'a;
+
+    fn from_ref<'a>(
+        context: RefLambdaContext<'a, E, R>,
+        env: &Arc<ContextEnv>,
+    ) -> Self::Context<'a>;
+}
+
+/// Passes a [`RefLambdaContext`] to the event handler. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RefContextKind;
+
+impl<E, R> ContextKind<E, R> for RefContextKind
+where
+    E: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+{
+    type Context<'a>
+        = RefLambdaContext<'a, E, R>
+    where
+        E: 'a,
+        R: 'a;
+
+    #[inline(always)]
+    fn from_ref<'a>(
+        context: RefLambdaContext<'a, E, R>,
+        _env: &Arc<ContextEnv>,
+    ) -> Self::Context<'a> {
+        context
+    }
+}
+
+/// Passes an [`OwnedLambdaContext`] to the event handler, allowing it to move the context into other threads or store it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OwnedContextKind;
+
+impl<E, R> ContextKind<E, R> for OwnedContextKind
+where
+    E: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+{
+    type Context<'a>
+        = OwnedLambdaContext
+    where
+        E: 'a,
+        R: 'a;
+
+    #[inline]
+    fn from_ref<'a>(
+        context: RefLambdaContext<'a, E, R>,
+        env: &Arc<ContextEnv>,
+    ) -> Self::Context<'a> {
+        OwnedLambdaContext::from_ref(&context, env.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A context implementing only the required methods.
+    struct MinimalContext;
+
+    impl LambdaContext for MinimalContext {
+        fn get_deadline(&self) -> Option<Duration> {
+            None
+        }
+        fn invoked_function_arn(&self) -> Option<&str> {
+            None
+        }
+        fn aws_request_id(&self) -> Option<&str> {
+            None
+        }
+        fn function_name(&self) -> Option<&str> {
+            None
+        }
+        fn function_version(&self) -> Option<&str> {
+            None
+        }
+        fn memory_limit_in_mb(&self) -> Option<usize> {
+            None
+        }
+        fn log_group_name(&self) -> Option<&str> {
+            None
+        }
+        fn log_stream_name(&self) -> Option<&str> {
+            None
+        }
+        fn cognito_identity(&self) -> Option<&str> {
+            None
+        }
+        fn client_context(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    #[test]
+    fn defaults_the_lifecycle_methods() {
+        let context = MinimalContext;
+        assert_eq!(context.initialization_type(), InitializationType::Unknown);
+        assert_eq!(context.invocation_index(), 0);
+        assert!(context.is_cold_start());
+        assert_eq!(context.init_duration(), Duration::ZERO);
+        assert_eq!(context.raw_trace_id(), None);
+        assert!(context.deadline_token().is_none());
+    }
 }
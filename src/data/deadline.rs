@@ -0,0 +1,120 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A cheap, copyable token that tracks the execution deadline of an invocation.
+///
+/// The token is derived from the `Lambda-Runtime-Deadline-Ms` header and a safety `margin`,
+/// and is considered expired once the current time is within `margin` of the deadline.
+/// This lets handlers stop long running work and return partial results before the function is forcibly stopped.
+///
+/// Being [`Copy`] and [`Send`], the token can be moved into spawned threads or stored alongside background work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineToken {
+    /// The deadline as a duration since the unix epoch.
+    deadline: Duration,
+    /// The safety margin subtracted from the deadline.
+    margin: Duration,
+}
+
+impl DeadlineToken {
+    /// Creates a new token from a `deadline` (duration since the unix epoch) and a safety `margin`.
+    pub fn new(deadline: Duration, margin: Duration) -> Self {
+        Self { deadline, margin }
+    }
+
+    /// Returns the deadline as a duration since the unix epoch.
+    #[inline(always)]
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    /// Returns the safety margin of the token.
+    #[inline(always)]
+    pub fn margin(&self) -> Duration {
+        self.margin
+    }
+
+    /// Returns the time left until the deadline minus the safety margin, or [`Duration::ZERO`] if the token is expired.
+    ///
+    /// Note that some APIs - such as [`std::net::TcpStream::set_read_timeout`] - reject a zero timeout,
+    /// callers should check [`DeadlineToken::is_expired`] first.
+    pub fn remaining(&self) -> Duration {
+        self.deadline
+            .saturating_sub(self.margin)
+            .saturating_sub(now_since_epoch())
+    }
+
+    /// Returns `true` if the current time is past the deadline minus the safety margin.
+    #[inline]
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Returns `true` if the token expires within `margin` from now, in addition to its own safety margin.
+    #[inline]
+    pub fn is_expired_within(&self, margin: Duration) -> bool {
+        self.remaining() <= margin
+    }
+
+    /// Returns an error if the token is expired, enabling early returns using the `?` operator.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_expired() {
+            Err(Error::new("Invocation deadline exceeded".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the current time as a duration since the unix epoch, or zero if the clock is set before it.
+fn now_since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a token whose deadline is `offset` from now, minus `margin`.
+    fn token_in(offset: Duration, margin: Duration) -> DeadlineToken {
+        DeadlineToken::new(now_since_epoch() + offset, margin)
+    }
+
+    #[test]
+    fn subtracts_the_margin_from_the_remaining_time() {
+        let token = token_in(Duration::from_secs(60), Duration::from_secs(10));
+        let remaining = token.remaining();
+        assert!(remaining <= Duration::from_secs(50));
+        assert!(remaining > Duration::from_secs(49));
+        assert!(!token.is_expired());
+        assert!(token.check().is_ok());
+
+        assert!(token.is_expired_within(Duration::from_secs(50)));
+        assert!(!token.is_expired_within(Duration::from_secs(45)));
+    }
+
+    #[test]
+    fn expires_within_the_margin_of_the_deadline() {
+        let token = token_in(Duration::from_secs(5), Duration::from_secs(10));
+        assert_eq!(token.remaining(), Duration::ZERO);
+        assert!(token.is_expired());
+        assert_eq!(
+            token.check().unwrap_err().to_string(),
+            "Invocation deadline exceeded"
+        );
+    }
+
+    #[test]
+    fn saturates_past_the_deadline() {
+        let token = DeadlineToken::new(Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(token.remaining(), Duration::ZERO);
+        assert!(token.is_expired());
+        assert!(token.is_expired_within(Duration::ZERO));
+    }
+}
@@ -2,12 +2,23 @@
 
 // `SPDX-License-Identifier: MIT OR Apache-2.0`
 
+use crate::error::Error;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+
+use serde_json::Value;
+
 /// An enum representing the `InitializationType` choices set as an env-var on the instance by AWS Lambda.
 /// See [Defined runtime environment variables](https://docs.aws.amazon.com/lambda/latest/dg/configuration-envvars.html#configuration-envvars-runtime).
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum InitializationType {
     OnDemand,
     ProvisionedConcurrency,
+    /// The execution environment was restored from a [SnapStart](https://docs.aws.amazon.com/lambda/latest/dg/snapstart.html) snapshot.
+    SnapStart,
+    #[default]
     Unknown,
 }
 
@@ -18,12 +29,92 @@ impl InitializationType {
         match itype {
             "on-demand" => Self::OnDemand,
             "provisioned-concurrency" => Self::ProvisionedConcurrency,
+            "snap-start" => Self::SnapStart,
             // Shouldn't reach here but if for some reason AWS doesn't get it right...
             _ => Self::Unknown,
         }
     }
 }
 
+/// The format of the function's logs, set in the `AWS_LAMBDA_LOG_FORMAT` env-var by AWS Lambda.
+/// See [Configuring advanced logging controls](https://docs.aws.amazon.com/lambda/latest/dg/monitoring-cloudwatchlogs-advanced.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    /// Returns the [`LogFormat`] value corresponding to the input string, defaulting to [`LogFormat::Text`].
+    fn from_string(format: &str) -> LogFormat {
+        if format.eq_ignore_ascii_case("json") {
+            Self::Json
+        } else {
+            Self::Text
+        }
+    }
+}
+
+/// The minimal level of the function's logs, set in the `AWS_LAMBDA_LOG_LEVEL` env-var by AWS Lambda.
+/// See [Configuring advanced logging controls](https://docs.aws.amazon.com/lambda/latest/dg/monitoring-cloudwatchlogs-advanced.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    /// Returns the [`LogLevel`] value corresponding to the input string, or `None` if it isn't a valid level.
+    fn from_string(level: &str) -> Option<LogLevel> {
+        match level.to_ascii_uppercase().as_str() {
+            "TRACE" => Some(Self::Trace),
+            "DEBUG" => Some(Self::Debug),
+            "INFO" => Some(Self::Info),
+            "WARN" => Some(Self::Warn),
+            "ERROR" => Some(Self::Error),
+            "FATAL" => Some(Self::Fatal),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the level as written in log records.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trace => "TRACE",
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+            Self::Fatal => "FATAL",
+        }
+    }
+}
+
+/// The behavior of the X-Ray SDKs when there is no active segment, set in the `AWS_XRAY_CONTEXT_MISSING` env-var.
+/// See [Environment variables](https://docs.aws.amazon.com/xray/latest/devguide/xray-sdk-java-configuration.html#xray-sdk-java-configuration-envvars).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContextMissingStrategy {
+    RuntimeError,
+    LogError,
+    IgnoreError,
+}
+
+impl ContextMissingStrategy {
+    /// Returns the [`ContextMissingStrategy`] value corresponding to the input string, or `None` if it isn't a valid strategy.
+    fn from_string(strategy: &str) -> Option<ContextMissingStrategy> {
+        match strategy.to_ascii_uppercase().as_str() {
+            "RUNTIME_ERROR" => Some(Self::RuntimeError),
+            "LOG_ERROR" => Some(Self::LogError),
+            "IGNORE_ERROR" => Some(Self::IgnoreError),
+            _ => None,
+        }
+    }
+}
+
 /// An interface trait for reading the environment variables set by the AWS Lambda service.
 ///
 /// Based on - [Defined runtime environment variables](https://docs.aws.amazon.com/lambda/latest/dg/configuration-envvars.html#configuration-envvars-runtime).
@@ -46,6 +137,52 @@ pub trait RuntimeEnvVars: Default {
     fn get_task_root(&self) -> Option<&str>;
     fn get_runtime_dir(&self) -> Option<&str>;
     fn get_tz(&self) -> Option<&str>;
+    fn get_log_format(&self) -> LogFormat {
+        self.get_other("AWS_LAMBDA_LOG_FORMAT")
+            .map_or(LogFormat::Text, LogFormat::from_string)
+    }
+    fn get_log_level(&self) -> Option<LogLevel> {
+        self.get_other("AWS_LAMBDA_LOG_LEVEL")
+            .and_then(LogLevel::from_string)
+    }
+    /// Returns the maximal number of concurrent invocations of an execution environment, set by AWS Lambda Managed Instances.
+    fn get_max_concurrency(&self) -> Option<usize> {
+        self.get_other("AWS_LAMBDA_MAX_CONCURRENCY")
+            .and_then(|v| v.parse::<usize>().ok())
+    }
+    fn get_xray_daemon_address(&self) -> Option<&str> {
+        self.get_other("AWS_XRAY_DAEMON_ADDRESS")
+    }
+    fn get_xray_context_missing(&self) -> Option<ContextMissingStrategy> {
+        self.get_other("AWS_XRAY_CONTEXT_MISSING")
+            .and_then(ContextMissingStrategy::from_string)
+    }
+    fn get_default_region(&self) -> Option<&str> {
+        self.get_other("AWS_DEFAULT_REGION")
+    }
+    fn get_exec_wrapper(&self) -> Option<&str> {
+        self.get_other("AWS_LAMBDA_EXEC_WRAPPER")
+    }
+    fn get_container_credentials_full_uri(&self) -> Option<&str> {
+        self.get_other("AWS_CONTAINER_CREDENTIALS_FULL_URI")
+    }
+    fn get_container_authorization_token(&self) -> Option<&str> {
+        self.get_other("AWS_CONTAINER_AUTHORIZATION_TOKEN")
+    }
+    fn get_lang(&self) -> Option<&str> {
+        self.get_other("LANG")
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.get_other("PATH")
+    }
+    /// Returns the value of any env-var as it was set when the env-vars were read,
+    /// including variables that don't have a dedicated accessor.
+    ///
+    /// The accessors above are implemented using this method by default.
+    /// The default implementation returns `None`, for implementations that don't keep the env-vars.
+    fn get_other(&self, _name: &str) -> Option<&str> {
+        None
+    }
     /// Returns the string value of an env-var `var_name` wrapped in an [`Option`],
     /// or `None` if the env-var is not set or the [`std::env::var`] function returns an error.
     fn get_var(var_name: &str) -> Option<String> {
@@ -58,7 +195,10 @@ pub trait RuntimeEnvVars: Default {
 
 /// A struct implementing [`RuntimeEnvVars`] by caching the default runtime env-vars,
 /// and supports a default initialization using std::env::var calls.
-#[derive(Debug, Clone)]
+///
+/// The secret access key, session token and container authorization token are redacted from the `Debug` output,
+/// including their entries in [`LambdaRuntimeEnv::vars`].
+#[derive(Clone)]
 pub struct LambdaRuntimeEnv {
     pub handler: Option<String>,
     // This value should be set by the runtime after each next invocation request where a new id is given
@@ -80,40 +220,318 @@ pub struct LambdaRuntimeEnv {
     pub task_root: Option<String>,
     pub runtime_dir: Option<String>,
     pub tz: Option<String>,
+    pub log_format: LogFormat,
+    pub log_level: Option<LogLevel>,
+    pub max_concurrency: Option<usize>,
+    pub xray_daemon_address: Option<String>,
+    pub xray_context_missing: Option<ContextMissingStrategy>,
+    pub default_region: Option<String>,
+    pub exec_wrapper: Option<String>,
+    pub container_credentials_full_uri: Option<String>,
+    pub container_authorization_token: Option<String>,
+    pub lang: Option<String>,
+    pub path: Option<String>,
+    /// All the env-vars of the process when they were read, returned by [`RuntimeEnvVars::get_other`].
+    pub vars: HashMap<String, String>,
 }
 
 impl LambdaRuntimeEnv {
     /// Constructs a new [`LambdaRuntimeEnv`] by reading the process' environment variables,
     /// and caching the [default env-vars](https://docs.aws.amazon.com/lambda/latest/dg/configuration-envvars.html#configuration-envvars-runtime).
     pub fn from_env() -> LambdaRuntimeEnv {
-        use std::env;
+        // Env-vars that are not valid unicode can't be read by `std::env::var` either.
+        let vars = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+        Self::from_map(vars)
+    }
+
+    /// Constructs a new [`LambdaRuntimeEnv`] from a map of env-var names to values instead of the process' environment,
+    /// e.g. for running the runtime locally or in tests.
+    pub fn from_map(vars: HashMap<String, String>) -> LambdaRuntimeEnv {
+        let var = |name: &str| vars.get(name).cloned();
         LambdaRuntimeEnv {
-            handler: env::var("_HANDLER").ok(),
-            region: env::var("AWS_REGION").ok(),
+            handler: var("_HANDLER"),
+            region: var("AWS_REGION"),
             trace_id: None,
-            execution_env: env::var("AWS_EXECUTION_ENV").ok(),
-            function_name: env::var("AWS_LAMBDA_FUNCTION_NAME").ok(),
-            function_memory_size: match env::var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE").ok() {
+            execution_env: var("AWS_EXECUTION_ENV"),
+            function_name: var("AWS_LAMBDA_FUNCTION_NAME"),
+            function_memory_size: match var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE") {
                 Some(v) => v.parse::<usize>().ok(),
                 None => None,
             },
-            function_version: env::var("AWS_LAMBDA_FUNCTION_VERSION").ok(),
-            initialization_type: match env::var("AWS_LAMBDA_INITIALIZATION_TYPE").ok() {
+            function_version: var("AWS_LAMBDA_FUNCTION_VERSION"),
+            initialization_type: match var("AWS_LAMBDA_INITIALIZATION_TYPE") {
                 Some(v) => InitializationType::from_string(&v),
                 None => InitializationType::Unknown,
             },
-            log_group_name: env::var("AWS_LAMBDA_LOG_GROUP_NAME").ok(),
-            log_stream_name: env::var("AWS_LAMBDA_LOG_STREAM_NAME").ok(),
-            access_key: env::var("AWS_ACCESS_KEY").ok(),
-            access_key_id: env::var("AWS_ACCESS_KEY_ID").ok(),
-            secret_access_key: env::var("AWS_SECRET_ACCESS_KEY").ok(),
-            session_token: env::var("AWS_SESSION_TOKEN").ok(),
-            runtime_api: env::var("AWS_LAMBDA_RUNTIME_API").ok(),
-            task_root: env::var("LAMBDA_TASK_ROOT").ok(),
-            runtime_dir: env::var("LAMBDA_RUNTIME_DIR").ok(),
-            tz: env::var("TZ").ok(),
+            log_group_name: var("AWS_LAMBDA_LOG_GROUP_NAME"),
+            log_stream_name: var("AWS_LAMBDA_LOG_STREAM_NAME"),
+            access_key: var("AWS_ACCESS_KEY"),
+            access_key_id: var("AWS_ACCESS_KEY_ID"),
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY"),
+            session_token: var("AWS_SESSION_TOKEN"),
+            runtime_api: var("AWS_LAMBDA_RUNTIME_API"),
+            task_root: var("LAMBDA_TASK_ROOT"),
+            runtime_dir: var("LAMBDA_RUNTIME_DIR"),
+            tz: var("TZ"),
+            log_format: match var("AWS_LAMBDA_LOG_FORMAT") {
+                Some(v) => LogFormat::from_string(&v),
+                None => LogFormat::Text,
+            },
+            log_level: match var("AWS_LAMBDA_LOG_LEVEL") {
+                Some(v) => LogLevel::from_string(&v),
+                None => None,
+            },
+            max_concurrency: match var("AWS_LAMBDA_MAX_CONCURRENCY") {
+                Some(v) => v.parse::<usize>().ok(),
+                None => None,
+            },
+            xray_daemon_address: var("AWS_XRAY_DAEMON_ADDRESS"),
+            xray_context_missing: match var("AWS_XRAY_CONTEXT_MISSING") {
+                Some(v) => ContextMissingStrategy::from_string(&v),
+                None => None,
+            },
+            default_region: var("AWS_DEFAULT_REGION"),
+            exec_wrapper: var("AWS_LAMBDA_EXEC_WRAPPER"),
+            container_credentials_full_uri: var("AWS_CONTAINER_CREDENTIALS_FULL_URI"),
+            container_authorization_token: var("AWS_CONTAINER_AUTHORIZATION_TOKEN"),
+            lang: var("LANG"),
+            path: var("PATH"),
+            vars,
         }
     }
+
+    /// Constructs a new [`LambdaRuntimeEnv`] from the env-vars defined in a file - see [`LambdaRuntimeEnvBuilder::with_file`].
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<LambdaRuntimeEnv, Error> {
+        read_vars_file(path.as_ref()).map(Self::from_map)
+    }
+
+    /// Returns a [`LambdaRuntimeEnvBuilder`] for constructing a [`LambdaRuntimeEnv`] with fake values for local runs and tests.
+    pub fn builder() -> LambdaRuntimeEnvBuilder {
+        LambdaRuntimeEnvBuilder::new()
+    }
+}
+
+/// Builds a [`LambdaRuntimeEnv`] from env-vars set using its `with_*` methods, instead of the process' environment.
+///
+/// The following env-vars are set to fake values unless they were set explicitly:
+/// - `AWS_LAMBDA_FUNCTION_NAME` - `local-function`.
+/// - `AWS_LAMBDA_FUNCTION_MEMORY_SIZE` - `128`.
+/// - `AWS_LAMBDA_FUNCTION_VERSION` - `$LATEST`.
+/// - `AWS_LAMBDA_INITIALIZATION_TYPE` - `on-demand`.
+/// - `AWS_REGION` and `AWS_DEFAULT_REGION` - `us-east-1`.
+/// - `AWS_LAMBDA_LOG_GROUP_NAME` - `/aws/lambda/` followed by the function name.
+/// - `AWS_LAMBDA_LOG_STREAM_NAME` - `local`.
+/// - `AWS_LAMBDA_RUNTIME_API` - `127.0.0.1:9001`, the address of the runtime API in the Lambda runtime interface emulator.
+#[derive(Debug, Clone, Default)]
+pub struct LambdaRuntimeEnvBuilder {
+    vars: HashMap<String, String>,
+}
+
+impl LambdaRuntimeEnvBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the env-var `name` to `value`.
+    pub fn with_var(mut self, name: &str, value: &str) -> Self {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets the env-vars defined in a file, overriding any previously set values.
+    ///
+    /// Files with a `.json` extension should contain a JSON object mapping env-var names to strings, numbers or booleans.
+    /// Other files are read in the dotenv format - a `NAME=value` pair on each line, optionally prefixed with `export`,
+    /// where values may be quoted and lines starting with `#` are ignored.
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Error> {
+        self.vars.extend(read_vars_file(path.as_ref())?);
+        Ok(self)
+    }
+
+    pub fn with_function_name(self, name: &str) -> Self {
+        self.with_var("AWS_LAMBDA_FUNCTION_NAME", name)
+    }
+
+    pub fn with_function_memory_size(self, size: usize) -> Self {
+        self.with_var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", &size.to_string())
+    }
+
+    pub fn with_handler(self, handler: &str) -> Self {
+        self.with_var("_HANDLER", handler)
+    }
+
+    /// Sets both `AWS_REGION` and `AWS_DEFAULT_REGION`.
+    pub fn with_region(self, region: &str) -> Self {
+        self.with_var("AWS_REGION", region)
+            .with_var("AWS_DEFAULT_REGION", region)
+    }
+
+    pub fn with_log_group_name(self, name: &str) -> Self {
+        self.with_var("AWS_LAMBDA_LOG_GROUP_NAME", name)
+    }
+
+    /// Sets the `host:port` of the runtime API, e.g. of an emulator.
+    pub fn with_runtime_api(self, address: &str) -> Self {
+        self.with_var("AWS_LAMBDA_RUNTIME_API", address)
+    }
+
+    pub fn build(self) -> LambdaRuntimeEnv {
+        let mut vars = self.vars;
+        let function_name = vars
+            .entry("AWS_LAMBDA_FUNCTION_NAME".to_string())
+            .or_insert_with(|| "local-function".to_string())
+            .clone();
+        let region = vars
+            .entry("AWS_REGION".to_string())
+            .or_insert_with(|| "us-east-1".to_string())
+            .clone();
+        let defaults = [
+            ("AWS_DEFAULT_REGION", region),
+            ("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", "128".to_string()),
+            ("AWS_LAMBDA_FUNCTION_VERSION", "$LATEST".to_string()),
+            ("AWS_LAMBDA_INITIALIZATION_TYPE", "on-demand".to_string()),
+            (
+                "AWS_LAMBDA_LOG_GROUP_NAME",
+                format!("/aws/lambda/{}", function_name),
+            ),
+            ("AWS_LAMBDA_LOG_STREAM_NAME", "local".to_string()),
+            ("AWS_LAMBDA_RUNTIME_API", "127.0.0.1:9001".to_string()),
+        ];
+        for (name, value) in defaults {
+            vars.entry(name.to_string()).or_insert(value);
+        }
+        LambdaRuntimeEnv::from_map(vars)
+    }
+}
+
+/// Reads the env-vars defined in a JSON or dotenv file, see [`LambdaRuntimeEnvBuilder::with_file`].
+fn read_vars_file(path: &Path) -> Result<HashMap<String, String>, Error> {
+    let contents = std::fs::read_to_string(path).map_err(|err| {
+        Error::new(format!(
+            "Failed reading env-vars file `{}`. {}",
+            path.display(),
+            err
+        ))
+    })?;
+    let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => parse_json_vars(&contents),
+        _ => parse_dotenv(&contents),
+    };
+    parsed.map_err(|err| {
+        Error::new(format!(
+            "Failed parsing env-vars file `{}`. {}",
+            path.display(),
+            err
+        ))
+    })
+}
+
+fn parse_json_vars(contents: &str) -> Result<HashMap<String, String>, String> {
+    let object = match serde_json::from_str::<Value>(contents).map_err(|err| err.to_string())? {
+        Value::Object(object) => object,
+        _ => return Err("Expected a JSON object".to_string()),
+    };
+    object
+        .into_iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => Ok((name, value)),
+            Value::Number(_) | Value::Bool(_) => Ok((name, value.to_string())),
+            _ => Err(format!("The value of `{}` is not a string", name)),
+        })
+        .collect()
+}
+
+fn parse_dotenv(contents: &str) -> Result<HashMap<String, String>, String> {
+    let mut vars = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("Expected `NAME=value` in line {}", index + 1))?;
+        let value = value.trim();
+        let value = if let Some(inner) = unquote(value, '"') {
+            unescape(inner)
+        } else {
+            unquote(value, '\'').unwrap_or(value).to_string()
+        };
+        vars.insert(name.trim().to_string(), value);
+    }
+    Ok(vars)
+}
+
+/// Returns the contents of `value` if it is enclosed in `quote`.
+fn unquote(value: &str, quote: char) -> Option<&str> {
+    value.strip_prefix(quote)?.strip_suffix(quote)
+}
+
+/// Replaces the escape sequences allowed in double quoted dotenv values.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => unescaped.push('\n'),
+            ('\\', Some(escaped @ ('"' | '\\'))) => unescaped.push(escaped),
+            _ => {
+                unescaped.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    unescaped
+}
+
+impl Debug for LambdaRuntimeEnv {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let redacted = |value: &Option<String>| value.as_ref().map(|_| "** redacted **");
+        // Any env-var may hold a secret, so only the names of the other env-vars are printed.
+        let vars: BTreeSet<&str> = self.vars.keys().map(String::as_str).collect();
+        f.debug_struct("LambdaRuntimeEnv")
+            .field("handler", &self.handler)
+            .field("trace_id", &self.trace_id)
+            .field("region", &self.region)
+            .field("execution_env", &self.execution_env)
+            .field("function_name", &self.function_name)
+            .field("function_memory_size", &self.function_memory_size)
+            .field("function_version", &self.function_version)
+            .field("initialization_type", &self.initialization_type)
+            .field("log_group_name", &self.log_group_name)
+            .field("log_stream_name", &self.log_stream_name)
+            .field("access_key", &self.access_key)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &redacted(&self.secret_access_key))
+            .field("session_token", &redacted(&self.session_token))
+            .field("runtime_api", &self.runtime_api)
+            .field("task_root", &self.task_root)
+            .field("runtime_dir", &self.runtime_dir)
+            .field("tz", &self.tz)
+            .field("log_format", &self.log_format)
+            .field("log_level", &self.log_level)
+            .field("max_concurrency", &self.max_concurrency)
+            .field("xray_daemon_address", &self.xray_daemon_address)
+            .field("xray_context_missing", &self.xray_context_missing)
+            .field("default_region", &self.default_region)
+            .field("exec_wrapper", &self.exec_wrapper)
+            .field(
+                "container_credentials_full_uri",
+                &self.container_credentials_full_uri,
+            )
+            .field(
+                "container_authorization_token",
+                &redacted(&self.container_authorization_token),
+            )
+            .field("lang", &self.lang)
+            .field("path", &self.path)
+            .field("vars", &vars)
+            .finish()
+    }
 }
 
 impl Default for LambdaRuntimeEnv {
@@ -212,8 +630,284 @@ impl RuntimeEnvVars for LambdaRuntimeEnv {
         self.tz.as_deref()
     }
 
+    #[inline(always)]
+    fn get_log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    #[inline(always)]
+    fn get_log_level(&self) -> Option<LogLevel> {
+        self.log_level
+    }
+
+    #[inline(always)]
+    fn get_max_concurrency(&self) -> Option<usize> {
+        self.max_concurrency
+    }
+
+    #[inline(always)]
+    fn get_xray_daemon_address(&self) -> Option<&str> {
+        self.xray_daemon_address.as_deref()
+    }
+
+    #[inline(always)]
+    fn get_xray_context_missing(&self) -> Option<ContextMissingStrategy> {
+        self.xray_context_missing
+    }
+
+    #[inline(always)]
+    fn get_default_region(&self) -> Option<&str> {
+        self.default_region.as_deref()
+    }
+
+    #[inline(always)]
+    fn get_exec_wrapper(&self) -> Option<&str> {
+        self.exec_wrapper.as_deref()
+    }
+
+    #[inline(always)]
+    fn get_container_credentials_full_uri(&self) -> Option<&str> {
+        self.container_credentials_full_uri.as_deref()
+    }
+
+    #[inline(always)]
+    fn get_container_authorization_token(&self) -> Option<&str> {
+        self.container_authorization_token.as_deref()
+    }
+
+    #[inline(always)]
+    fn get_lang(&self) -> Option<&str> {
+        self.lang.as_deref()
+    }
+
+    #[inline(always)]
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    #[inline]
+    fn get_other(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|value| value.as_str())
+    }
+
     #[inline]
     fn set_trace_id(&mut self, new_id: Option<&str>) {
         self.trace_id = new_id.map(|v| v.to_string());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    /// A file in the temp dir, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("rtlambda-{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_dotenv_files() {
+        let contents = r#"
+# A comment
+PLAIN=value
+export EXPORTED=exported
+  SPACED = spaced value
+DOUBLE="line\nbreak \"quoted\" back\\slash \t"
+SINGLE='no \n escapes'
+EMPTY=
+EQUALS=a=b
+UNTERMINATED="open
+"#;
+        assert_eq!(
+            parse_dotenv(contents).unwrap(),
+            vars(&[
+                ("PLAIN", "value"),
+                ("EXPORTED", "exported"),
+                ("SPACED", "spaced value"),
+                ("DOUBLE", "line\nbreak \"quoted\" back\\slash \\t"),
+                ("SINGLE", "no \\n escapes"),
+                ("EMPTY", ""),
+                ("EQUALS", "a=b"),
+                ("UNTERMINATED", "\"open"),
+            ])
+        );
+        assert_eq!(
+            parse_dotenv("NAME=value\n\nINVALID\n").unwrap_err(),
+            "Expected `NAME=value` in line 3"
+        );
+    }
+
+    #[test]
+    fn parses_json_files() {
+        assert_eq!(
+            parse_json_vars(r#"{"NAME": "value", "SIZE": 128, "ENABLED": true, "RATIO": 0.5}"#)
+                .unwrap(),
+            vars(&[
+                ("NAME", "value"),
+                ("SIZE", "128"),
+                ("ENABLED", "true"),
+                ("RATIO", "0.5")
+            ])
+        );
+        assert_eq!(
+            parse_json_vars(r#"{"LIST": [1]}"#).unwrap_err(),
+            "The value of `LIST` is not a string"
+        );
+        assert_eq!(
+            parse_json_vars(r#"{"NAME": null}"#).unwrap_err(),
+            "The value of `NAME` is not a string"
+        );
+        assert_eq!(
+            parse_json_vars(r#"["NAME"]"#).unwrap_err(),
+            "Expected a JSON object"
+        );
+        assert!(parse_json_vars("NAME=value").is_err());
+    }
+
+    #[test]
+    fn reads_files_by_extension() {
+        let json = TempFile::new(
+            "env.json",
+            r#"{"_HANDLER": "orders", "AWS_LAMBDA_FUNCTION_MEMORY_SIZE": 256}"#,
+        );
+        let env = LambdaRuntimeEnv::from_file(&json.0).unwrap();
+        assert_eq!(env.get_handler(), Some("orders"));
+        assert_eq!(env.get_function_memory_size(), Some(256));
+
+        let dotenv = TempFile::new("env", "_HANDLER=payments\nAWS_REGION='eu-west-1'\n");
+        let env = LambdaRuntimeEnv::from_file(&dotenv.0).unwrap();
+        assert_eq!(env.get_handler(), Some("payments"));
+        assert_eq!(env.get_region(), Some("eu-west-1"));
+
+        // A `.json` file is never read as dotenv.
+        let invalid = TempFile::new("invalid.json", "_HANDLER=orders");
+        let err = LambdaRuntimeEnv::from_file(&invalid.0).unwrap_err();
+        assert!(err.to_string().starts_with("Failed parsing env-vars file"));
+
+        let missing = std::env::temp_dir().join("rtlambda-missing.env");
+        let err = LambdaRuntimeEnv::from_file(missing).unwrap_err();
+        assert!(err.to_string().starts_with("Failed reading env-vars file"));
+    }
+
+    #[test]
+    fn reads_the_default_env_vars_from_a_map() {
+        let env = LambdaRuntimeEnv::from_map(vars(&[
+            ("_HANDLER", "orders"),
+            ("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", "512"),
+            ("AWS_LAMBDA_INITIALIZATION_TYPE", "snap-start"),
+            ("AWS_LAMBDA_LOG_FORMAT", "JSON"),
+            ("AWS_LAMBDA_MAX_CONCURRENCY", "not a number"),
+            ("APP_TABLE", "orders-table"),
+        ]));
+        assert_eq!(env.get_handler(), Some("orders"));
+        assert_eq!(env.get_function_memory_size(), Some(512));
+        assert_eq!(env.get_initialization_type(), InitializationType::SnapStart);
+        assert_eq!(env.get_log_format(), LogFormat::Json);
+        assert_eq!(env.get_max_concurrency(), None);
+        assert_eq!(env.get_region(), None);
+        assert_eq!(env.get_other("APP_TABLE"), Some("orders-table"));
+        assert_eq!(env.get_other("_HANDLER"), Some("orders"));
+        assert_eq!(env.get_other("MISSING"), None);
+
+        let env = LambdaRuntimeEnv::from_map(HashMap::new());
+        assert_eq!(env.get_initialization_type(), InitializationType::Unknown);
+        assert_eq!(env.get_log_format(), LogFormat::Text);
+    }
+
+    #[test]
+    fn builds_with_fake_defaults() {
+        let env = LambdaRuntimeEnv::builder().build();
+        assert_eq!(env.get_function_name(), Some("local-function"));
+        assert_eq!(env.get_function_memory_size(), Some(128));
+        assert_eq!(env.get_function_version(), Some("$LATEST"));
+        assert_eq!(env.get_initialization_type(), InitializationType::OnDemand);
+        assert_eq!(env.get_region(), Some("us-east-1"));
+        assert_eq!(env.get_default_region(), Some("us-east-1"));
+        assert_eq!(env.get_log_group_name(), Some("/aws/lambda/local-function"));
+        assert_eq!(env.get_log_stream_name(), Some("local"));
+        assert_eq!(env.get_runtime_api(), Some("127.0.0.1:9001"));
+        assert_eq!(env.get_handler(), None);
+    }
+
+    #[test]
+    fn overrides_the_fake_defaults() {
+        let file = TempFile::new(
+            "builder.env",
+            "AWS_LAMBDA_FUNCTION_NAME=from-file\nAWS_LAMBDA_LOG_STREAM_NAME=file-stream\n",
+        );
+        let env = LambdaRuntimeEnv::builder()
+            .with_function_name("from-builder")
+            .with_region("eu-west-1")
+            .with_var("AWS_LAMBDA_LOG_STREAM_NAME", "builder-stream")
+            .with_file(&file.0)
+            .unwrap()
+            .with_runtime_api("localhost:8080")
+            .build();
+        // Later calls override earlier ones, and the defaults derived from other env-vars follow them.
+        assert_eq!(env.get_function_name(), Some("from-file"));
+        assert_eq!(env.get_log_group_name(), Some("/aws/lambda/from-file"));
+        assert_eq!(env.get_log_stream_name(), Some("file-stream"));
+        assert_eq!(env.get_region(), Some("eu-west-1"));
+        assert_eq!(env.get_default_region(), Some("eu-west-1"));
+        assert_eq!(env.get_runtime_api(), Some("localhost:8080"));
+
+        let env = LambdaRuntimeEnv::builder()
+            .with_file(&file.0)
+            .unwrap()
+            .with_function_name("from-builder")
+            .with_log_group_name("/custom/group")
+            .with_var("AWS_REGION", "ap-south-1")
+            .build();
+        assert_eq!(env.get_function_name(), Some("from-builder"));
+        assert_eq!(env.get_log_group_name(), Some("/custom/group"));
+        assert_eq!(env.get_log_stream_name(), Some("file-stream"));
+        // `AWS_DEFAULT_REGION` defaults to `AWS_REGION`.
+        assert_eq!(env.get_default_region(), Some("ap-south-1"));
+    }
+
+    #[test]
+    fn redacts_secrets_from_debug_output() {
+        let env = LambdaRuntimeEnv::builder()
+            .with_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE")
+            .with_var("AWS_SECRET_ACCESS_KEY", "secret-access-key")
+            .with_var("AWS_SESSION_TOKEN", "session-token")
+            .with_var("AWS_CONTAINER_AUTHORIZATION_TOKEN", "authorization-token")
+            .with_var("DATABASE_PASSWORD", "database-password")
+            .build();
+        let debug = format!("{:?}", env);
+
+        assert!(debug.contains("AKIDEXAMPLE"));
+        assert!(debug.contains("\"DATABASE_PASSWORD\""));
+        for secret in [
+            "secret-access-key",
+            "session-token",
+            "authorization-token",
+            "database-password",
+        ] {
+            assert!(!debug.contains(secret), "`{}` is not redacted", secret);
+        }
+        assert_eq!(env.get_session_token(), Some("session-token"));
+    }
+}
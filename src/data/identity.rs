@@ -0,0 +1,227 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::error::Error;
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+/// Information about the client application that invoked the function using the AWS Mobile SDK,
+/// read from the `Lambda-Runtime-Client-Context` header.
+///
+/// See [Context object](https://docs.aws.amazon.com/lambda/latest/dg/python-context.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientContext {
+    pub client: ClientApplication,
+    /// Custom values set by the client application.
+    pub custom: HashMap<String, String>,
+    /// Environment information provided by the AWS SDK.
+    pub env: HashMap<String, String>,
+}
+
+/// The client application section of a [`ClientContext`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientApplication {
+    pub installation_id: Option<String>,
+    pub app_title: Option<String>,
+    pub app_version_name: Option<String>,
+    pub app_version_code: Option<String>,
+    pub app_package_name: Option<String>,
+}
+
+impl ClientContext {
+    /// Parses the JSON value of the `Lambda-Runtime-Client-Context` header.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let value = parse_object(json, "client context")?;
+        let client = match value.get("client") {
+            Some(Value::Object(client)) => ClientApplication {
+                installation_id: string_field(client, "installation_id")?,
+                app_title: string_field(client, "app_title")?,
+                app_version_name: string_field(client, "app_version_name")?,
+                app_version_code: string_field(client, "app_version_code")?,
+                app_package_name: string_field(client, "app_package_name")?,
+            },
+            None | Some(Value::Null) => ClientApplication::default(),
+            Some(_) => return Err(invalid_field("client")),
+        };
+
+        Ok(Self {
+            client,
+            custom: string_map(&value, "custom")?,
+            env: string_map(&value, "env")?,
+        })
+    }
+}
+
+/// Information about the Amazon Cognito identity that authorized the request,
+/// read from the `Lambda-Runtime-Cognito-Identity` header.
+///
+/// See [Context object](https://docs.aws.amazon.com/lambda/latest/dg/python-context.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CognitoIdentity {
+    pub identity_id: Option<String>,
+    pub identity_pool_id: Option<String>,
+}
+
+impl CognitoIdentity {
+    /// Parses the JSON value of the `Lambda-Runtime-Cognito-Identity` header.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let value = parse_object(json, "cognito identity")?;
+        Ok(Self {
+            identity_id: string_field(&value, "cognitoIdentityId")?,
+            identity_pool_id: string_field(&value, "cognitoIdentityPoolId")?,
+        })
+    }
+}
+
+fn parse_object(json: &str, name: &str) -> Result<Map<String, Value>, Error> {
+    match serde_json::from_str::<Value>(json) {
+        Ok(Value::Object(value)) => Ok(value),
+        Ok(_) => Err(Error::new(format!(
+            "Failed parsing {}. Expected a JSON object",
+            name
+        ))),
+        Err(err) => Err(Error::new(format!("Failed parsing {}. {}", name, err))),
+    }
+}
+
+fn invalid_field(name: &str) -> Error {
+    Error::new(format!("Invalid type for field `{}`", name))
+}
+
+fn string_field(object: &Map<String, Value>, name: &str) -> Result<Option<String>, Error> {
+    match object.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(v)) => Ok(Some(v.clone())),
+        Some(_) => Err(invalid_field(name)),
+    }
+}
+
+/// Reads a JSON object field as a map, converting non-string values to their JSON representation.
+fn string_map(object: &Map<String, Value>, name: &str) -> Result<HashMap<String, String>, Error> {
+    match object.get(name) {
+        None | Some(Value::Null) => Ok(HashMap::new()),
+        Some(Value::Object(map)) => Ok(map
+            .iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    Value::String(v) => v.clone(),
+                    other => other.to_string(),
+                };
+                (k.clone(), v)
+            })
+            .collect()),
+        Some(_) => Err(invalid_field(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_full_client_context() {
+        let context = ClientContext::from_json(
+            r#"{
+                "client": {
+                    "installation_id": "d7a1c2",
+                    "app_title": "Notes",
+                    "app_version_name": "1.2.0",
+                    "app_version_code": "120",
+                    "app_package_name": "com.example.notes"
+                },
+                "custom": {"tier": "premium", "retries": 3},
+                "env": {"platform": "Android", "locale": "en_US"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            context.client,
+            ClientApplication {
+                installation_id: Some("d7a1c2".to_string()),
+                app_title: Some("Notes".to_string()),
+                app_version_name: Some("1.2.0".to_string()),
+                app_version_code: Some("120".to_string()),
+                app_package_name: Some("com.example.notes".to_string()),
+            }
+        );
+        assert_eq!(context.custom["tier"], "premium");
+        // Non-string values are kept as JSON.
+        assert_eq!(context.custom["retries"], "3");
+        assert_eq!(context.env["platform"], "Android");
+        assert_eq!(context.env.len(), 2);
+    }
+
+    #[test]
+    fn parses_a_partial_client_context() {
+        let context =
+            ClientContext::from_json(r#"{"client": {"app_title": "Notes"}, "env": null}"#).unwrap();
+        assert_eq!(context.client.app_title.as_deref(), Some("Notes"));
+        assert_eq!(context.client.installation_id, None);
+        assert!(context.custom.is_empty());
+        assert!(context.env.is_empty());
+
+        assert_eq!(
+            ClientContext::from_json("{}").unwrap(),
+            ClientContext::default()
+        );
+    }
+
+    #[test]
+    fn rejects_a_malformed_client_context() {
+        let err = ClientContext::from_json("{\"client\":").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Failed parsing client context."));
+        assert_eq!(
+            ClientContext::from_json("[]").unwrap_err().to_string(),
+            "Failed parsing client context. Expected a JSON object"
+        );
+        assert_eq!(
+            ClientContext::from_json(r#"{"client": "Notes"}"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid type for field `client`"
+        );
+        assert_eq!(
+            ClientContext::from_json(r#"{"client": {"app_title": 1}}"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid type for field `app_title`"
+        );
+        assert_eq!(
+            ClientContext::from_json(r#"{"custom": ["tier"]}"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid type for field `custom`"
+        );
+    }
+
+    #[test]
+    fn parses_cognito_identities() {
+        let identity = CognitoIdentity::from_json(
+            r#"{"cognitoIdentityId": "us-east-1:1a2b", "cognitoIdentityPoolId": "us-east-1:3c4d"}"#,
+        )
+        .unwrap();
+        assert_eq!(identity.identity_id.as_deref(), Some("us-east-1:1a2b"));
+        assert_eq!(identity.identity_pool_id.as_deref(), Some("us-east-1:3c4d"));
+
+        let identity =
+            CognitoIdentity::from_json(r#"{"cognitoIdentityId": "us-east-1:1a2b"}"#).unwrap();
+        assert_eq!(identity.identity_id.as_deref(), Some("us-east-1:1a2b"));
+        assert_eq!(identity.identity_pool_id, None);
+
+        assert!(CognitoIdentity::from_json("not json")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Failed parsing cognito identity."));
+        assert_eq!(
+            CognitoIdentity::from_json(r#"{"cognitoIdentityPoolId": false}"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid type for field `cognitoIdentityPoolId`"
+        );
+    }
+}
@@ -4,7 +4,15 @@
 
 /// Defines the interface of the context object and provides an implementation for it.
 pub mod context;
+/// Defines a token used to track the execution deadline of an invocation.
+pub mod deadline;
 /// Defines an interface for reading env-vars and provides an implementation for it.
 pub mod env;
+/// Defines the client context and Cognito identity types exposed by the context object.
+pub mod identity;
 /// Defines the interface used to read a response from the Lambda API.
 pub mod response;
+/// Conversions between days since the epoch and civil dates.
+pub(crate) mod time;
+/// Defines a parsed X-Ray trace header type and helpers for propagating it.
+pub mod trace;
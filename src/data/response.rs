@@ -11,6 +11,7 @@ pub static AWS_TRACE_ID: &str = "Lambda-Runtime-Trace-Id";
 pub static AWS_CLIENT_CTX: &str = "Lambda-Runtime-Client-Context";
 pub static AWS_COG_ID: &str = "Lambda-Runtime-Cognito-Identity";
 pub static AWS_FUNC_ERR_TYPE: &str = "Lambda-Runtime-Function-Error-Type";
+pub static AWS_FUNC_XRAY_ERR_CAUSE: &str = "Lambda-Runtime-Function-XRay-Error-Cause";
 
 //Based on [https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html#runtimes-api-next]
 /// An interface trait representing a response from the [AWS Lambda runtime API](https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html).
@@ -70,6 +71,55 @@ pub trait LambdaAPIResponse {
     }
 
     fn is_err(&self) -> bool {
-        matches!(self.get_status_code(), 400..=499 | 500..=599)
+        matches!(self.get_status_code(), 400..=599)
+    }
+}
+
+/// A [`LambdaAPIResponse`] holding the values it returns, used to create context objects in unit tests.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TestResponse {
+    pub body: Option<String>,
+    pub status_code: u16,
+    pub aws_request_id: Option<String>,
+    pub deadline: Option<Duration>,
+    pub invoked_function_arn: Option<String>,
+    pub trace_id: Option<String>,
+    pub client_context: Option<String>,
+    pub cognito_identity: Option<String>,
+}
+
+#[cfg(test)]
+impl LambdaAPIResponse for TestResponse {
+    fn get_body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    fn get_status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    fn aws_request_id(&self) -> Option<&str> {
+        self.aws_request_id.as_deref()
+    }
+
+    fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    fn invoked_function_arn(&self) -> Option<&str> {
+        self.invoked_function_arn.as_deref()
+    }
+
+    fn trace_id(&self) -> Option<&str> {
+        self.trace_id.as_deref()
+    }
+
+    fn client_context(&self) -> Option<&str> {
+        self.client_context.as_deref()
+    }
+
+    fn cognito_identity(&self) -> Option<&str> {
+        self.cognito_identity.as_deref()
     }
 }
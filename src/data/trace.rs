@@ -0,0 +1,384 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::error::Error;
+
+use std::collections::hash_map::RandomState;
+use std::fmt::{Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The sampling decision of an [X-Ray trace header](https://docs.aws.amazon.com/xray/latest/devguide/xray-concepts.html#xray-concepts-tracingheader).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingDecision {
+    /// `Sampled=1`
+    Sampled,
+    /// `Sampled=0`
+    NotSampled,
+    /// `Sampled=?` - the downstream service should make the sampling decision.
+    Requested,
+}
+
+/// A parsed [X-Ray trace header](https://docs.aws.amazon.com/xray/latest/devguide/xray-concepts.html#xray-concepts-tracingheader),
+/// as received in the `Lambda-Runtime-Trace-Id` header and set to the `_X_AMZN_TRACE_ID` env-var.
+///
+/// Rendering the header using [`Display`] yields a value suitable for the `X-Amzn-Trace-Id` header of outbound requests.
+/// Fields that are not recognized are kept and rendered back in their original order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceHeader {
+    /// The trace id, e.g. `1-5759e988-bd862e3fe1be46a994272793`.
+    pub root: String,
+    /// The id of the parent segment, 16 hex digits.
+    pub parent: Option<String>,
+    pub sampled: Option<SamplingDecision>,
+    pub lineage: Option<String>,
+    /// Any other `key=value` pairs of the header.
+    pub extra: Vec<(String, String)>,
+}
+
+impl TraceHeader {
+    /// Parses a trace header of the form `Root=...;Parent=...;Sampled=...;Lineage=...`.
+    pub fn parse(header: &str) -> Result<Self, Error> {
+        let mut root = None;
+        let mut parent = None;
+        let mut sampled = None;
+        let mut lineage = None;
+        let mut extra = Vec::new();
+
+        for field in header.split(';').map(str::trim).filter(|f| !f.is_empty()) {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| Error::new(format!("Invalid trace header field `{}`", field)))?;
+            match key {
+                "Root" => root = Some(value.to_string()),
+                "Parent" => parent = Some(value.to_string()),
+                "Sampled" => {
+                    sampled = Some(match value {
+                        "1" => SamplingDecision::Sampled,
+                        "0" => SamplingDecision::NotSampled,
+                        "?" => SamplingDecision::Requested,
+                        _ => {
+                            return Err(Error::new(format!(
+                                "Invalid trace header sampling decision `{}`",
+                                value
+                            )))
+                        }
+                    })
+                }
+                "Lineage" => lineage = Some(value.to_string()),
+                _ => extra.push((key.to_string(), value.to_string())),
+            }
+        }
+
+        Ok(Self {
+            root: root.ok_or_else(|| Error::new("Missing trace header root".to_string()))?,
+            parent,
+            sampled,
+            lineage,
+            extra,
+        })
+    }
+
+    /// Creates a header for a new trace with a random trace id and no parent.
+    pub fn new_root(sampled: Option<SamplingDecision>) -> Self {
+        Self {
+            root: new_trace_id(),
+            parent: None,
+            sampled,
+            lineage: None,
+            extra: Vec::new(),
+        }
+    }
+
+    /// Returns a copy of this header with `segment_id` as the parent, used to propagate the trace from a (sub)segment to a downstream call.
+    pub fn child(&self, segment_id: &str) -> Self {
+        Self {
+            parent: Some(segment_id.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns `true` only if the header carries a positive sampling decision.
+    #[inline]
+    pub fn is_sampled(&self) -> bool {
+        self.sampled == Some(SamplingDecision::Sampled)
+    }
+
+    /// Converts the header into a [W3C `traceparent`](https://www.w3.org/TR/trace-context/#traceparent-header) header value.
+    ///
+    /// Fails if the root isn't a valid X-Ray trace id or the header has no parent.
+    pub fn to_traceparent(&self) -> Result<String, Error> {
+        let trace_id = match self.root.split('-').collect::<Vec<_>>().as_slice() {
+            ["1", epoch, random] if is_hex(epoch, 8) && is_hex(random, 24) => {
+                format!("{}{}", epoch, random)
+            }
+            _ => {
+                return Err(Error::new(format!(
+                    "Invalid X-Ray trace id `{}`",
+                    self.root
+                )))
+            }
+        };
+        let parent = match self.parent.as_deref() {
+            Some(parent) if is_hex(parent, 16) => parent,
+            Some(parent) => return Err(Error::new(format!("Invalid parent id `{}`", parent))),
+            None => return Err(Error::new("Missing parent id".to_string())),
+        };
+        let flags = if self.is_sampled() { "01" } else { "00" };
+
+        Ok(format!(
+            "00-{}-{}-{}",
+            trace_id.to_lowercase(),
+            parent.to_lowercase(),
+            flags
+        ))
+    }
+
+    /// Parses a [W3C `traceparent`](https://www.w3.org/TR/trace-context/#traceparent-header) header value into an X-Ray trace header.
+    /// As required by the spec, a trace id or parent id made only of zeros is rejected as invalid.
+    pub fn from_traceparent(traceparent: &str) -> Result<Self, Error> {
+        let invalid = || Error::new(format!("Invalid traceparent `{}`", traceparent));
+        match traceparent.trim().split('-').collect::<Vec<_>>().as_slice() {
+            [version, trace_id, parent, flags]
+                if is_hex(version, 2)
+                    && *version != "ff"
+                    && is_hex(trace_id, 32)
+                    && !is_zero(trace_id)
+                    && is_hex(parent, 16)
+                    && !is_zero(parent)
+                    && is_hex(flags, 2) =>
+            {
+                let flags = u8::from_str_radix(flags, 16).map_err(|_| invalid())?;
+                Ok(Self {
+                    root: format!("1-{}-{}", &trace_id[..8], &trace_id[8..]),
+                    parent: Some(parent.to_string()),
+                    sampled: Some(match flags & 1 {
+                        1 => SamplingDecision::Sampled,
+                        _ => SamplingDecision::NotSampled,
+                    }),
+                    lineage: None,
+                    extra: Vec::new(),
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Generates a random 64-bit segment id as 16 hex digits.
+    pub fn new_segment_id() -> String {
+        format!("{:016x}", random_u64())
+    }
+}
+
+impl Display for TraceHeader {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Root={}", &self.root)?;
+        if let Some(parent) = &self.parent {
+            write!(f, ";Parent={}", parent)?;
+        }
+        if let Some(sampled) = self.sampled {
+            let value = match sampled {
+                SamplingDecision::Sampled => "1",
+                SamplingDecision::NotSampled => "0",
+                SamplingDecision::Requested => "?",
+            };
+            write!(f, ";Sampled={}", value)?;
+        }
+        if let Some(lineage) = &self.lineage {
+            write!(f, ";Lineage={}", lineage)?;
+        }
+        for (key, value) in &self.extra {
+            write!(f, ";{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TraceHeader {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Generates a new X-Ray trace id from the current time and 96 random bits.
+fn new_trace_id() -> String {
+    let epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!(
+        "1-{:08x}-{:08x}{:016x}",
+        epoch as u32,
+        random_u64() as u32,
+        random_u64()
+    )
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn is_zero(value: &str) -> bool {
+    value.bytes().all(|b| b == b'0')
+}
+
+/// An additional seed mixed into [`random_u64`], replaced by [`reseed_random`].
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a random number, using the randomly seeded hasher of the standard library to avoid depending on an RNG crate.
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(SEED.load(Ordering::Relaxed));
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+    );
+    hasher.finish()
+}
+
+/// Replaces the seed mixed into [`random_u64`].
+///
+/// The keys of the standard library's hasher are part of the process memory, so all the execution environments restored
+/// from the same SnapStart snapshot would otherwise generate ids from the same keys.
+pub(crate) fn reseed_random() {
+    use std::io::Read;
+
+    let mut bytes = [0u8; 8];
+    let seed = match std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes))
+    {
+        Ok(()) => u64::from_ne_bytes(bytes),
+        Err(_) => {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+            nanos ^ u64::from(std::process::id()).rotate_left(32)
+        }
+    };
+    SEED.store(seed, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str =
+        "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1;Lineage=a87bd80c:1|68fd508a:5;Self=1-abc";
+
+    #[test]
+    fn parses_trace_headers() {
+        let header = TraceHeader::parse(HEADER).unwrap();
+        assert_eq!(header.root, "1-5759e988-bd862e3fe1be46a994272793");
+        assert_eq!(header.parent.as_deref(), Some("53995c3f42cd8ad8"));
+        assert_eq!(header.sampled, Some(SamplingDecision::Sampled));
+        assert!(header.is_sampled());
+        assert_eq!(header.lineage.as_deref(), Some("a87bd80c:1|68fd508a:5"));
+        assert_eq!(header.extra, [("Self".to_string(), "1-abc".to_string())]);
+
+        let header: TraceHeader = " Root=1-5759e988-bd862e3fe1be46a994272793 ; Sampled=? ;"
+            .parse()
+            .unwrap();
+        assert_eq!(header.parent, None);
+        assert_eq!(header.sampled, Some(SamplingDecision::Requested));
+        assert!(!header.is_sampled());
+        assert_eq!(header.lineage, None);
+    }
+
+    #[test]
+    fn rejects_malformed_trace_headers() {
+        assert_eq!(
+            TraceHeader::parse("Parent=53995c3f42cd8ad8;Sampled=1")
+                .unwrap_err()
+                .to_string(),
+            "Missing trace header root"
+        );
+        assert_eq!(
+            TraceHeader::parse("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled")
+                .unwrap_err()
+                .to_string(),
+            "Invalid trace header field `Sampled`"
+        );
+        assert_eq!(
+            TraceHeader::parse("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=yes")
+                .unwrap_err()
+                .to_string(),
+            "Invalid trace header sampling decision `yes`"
+        );
+    }
+
+    #[test]
+    fn renders_the_parsed_fields_back() {
+        assert_eq!(TraceHeader::parse(HEADER).unwrap().to_string(), HEADER);
+
+        let header = TraceHeader::parse("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=0")
+            .unwrap()
+            .child("0123456789abcdef");
+        assert_eq!(
+            header.to_string(),
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=0123456789abcdef;Sampled=0"
+        );
+        assert_eq!(TraceHeader::parse(&header.to_string()).unwrap(), header);
+    }
+
+    #[test]
+    fn converts_to_and_from_traceparent() {
+        let header = TraceHeader::parse(HEADER).unwrap();
+        let traceparent = header.to_traceparent().unwrap();
+        assert_eq!(
+            traceparent,
+            "00-5759e988bd862e3fe1be46a994272793-53995c3f42cd8ad8-01"
+        );
+
+        let parsed = TraceHeader::from_traceparent(&traceparent).unwrap();
+        assert_eq!(parsed.root, header.root);
+        assert_eq!(parsed.parent, header.parent);
+        assert_eq!(parsed.sampled, Some(SamplingDecision::Sampled));
+
+        let parsed = TraceHeader::from_traceparent(
+            "00-5759e988bd862e3fe1be46a994272793-53995c3f42cd8ad8-02",
+        )
+        .unwrap();
+        assert_eq!(parsed.sampled, Some(SamplingDecision::NotSampled));
+        assert_eq!(
+            parsed.to_traceparent().unwrap(),
+            "00-5759e988bd862e3fe1be46a994272793-53995c3f42cd8ad8-00"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_traceparent_conversions() {
+        let header = TraceHeader::parse("Root=1-5759e988-bd862e3fe1be46a994272793").unwrap();
+        assert_eq!(
+            header.to_traceparent().unwrap_err().to_string(),
+            "Missing parent id"
+        );
+        let header = TraceHeader::parse("Root=1-5759e988;Parent=53995c3f42cd8ad8").unwrap();
+        assert_eq!(
+            header.to_traceparent().unwrap_err().to_string(),
+            "Invalid X-Ray trace id `1-5759e988`"
+        );
+
+        for traceparent in [
+            "00-5759e988bd862e3fe1be46a994272793-53995c3f42cd8ad8",
+            "ff-5759e988bd862e3fe1be46a994272793-53995c3f42cd8ad8-01",
+            "00-00000000000000000000000000000000-53995c3f42cd8ad8-01",
+            "00-5759e988bd862e3fe1be46a994272793-0000000000000000-01",
+            "00-5759e988bd862e3fe1be46a99427279z-53995c3f42cd8ad8-01",
+        ] {
+            assert!(
+                TraceHeader::from_traceparent(traceparent).is_err(),
+                "{}",
+                traceparent
+            );
+        }
+    }
+}
@@ -2,8 +2,11 @@
 
 // `SPDX-License-Identifier: MIT OR Apache-2.0`
 
+use std::backtrace::{Backtrace, BacktraceStatus};
 use std::fmt::{Display, Formatter};
 
+use serde_json::{json, Value};
+
 #[derive(Clone, Debug)]
 pub struct Error {
     msg: String,
@@ -21,4 +24,333 @@ impl Display for Error {
     }
 }
 
+impl std::error::Error for Error {}
+
+/// Allows handlers using `String` errors to accept errors raised by the runtime's helpers, e.g. when deserializing events.
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.msg
+    }
+}
+
 pub static CONTAINER_ERR: &str = "Container error. Non-recoverable state.";
+
+/// The maximal size of the X-Ray error cause sent in the `Lambda-Runtime-Function-XRay-Error-Cause` header.
+pub const MAX_XRAY_ERROR_CAUSE_SIZE: usize = 1024 * 1024;
+
+/// A single frame of an [`ErrorRequest`] stack trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StackFrame {
+    /// The function name.
+    pub label: String,
+    /// The source file, if known.
+    pub path: Option<String>,
+    /// The line in the source file, if known.
+    pub line: Option<u32>,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match (&self.path, self.line) {
+            (Some(path), Some(line)) => write!(f, "{} ({}:{})", &self.label, path, line),
+            (Some(path), None) => write!(f, "{} ({})", &self.label, path),
+            _ => write!(f, "{}", &self.label),
+        }
+    }
+}
+
+/// A structured error reported to the Lambda service,
+/// see [ErrorRequest](https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html#runtimes-api-invokeerror).
+///
+/// Besides the JSON request body, the error is also rendered as an
+/// [X-Ray error cause](https://docs.aws.amazon.com/xray/latest/devguide/xray-api-segmentdocuments.html#api-segmentdocuments-errors)
+/// so that its details show up in the trace of the failed invocation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorRequest {
+    pub error_type: String,
+    pub error_message: String,
+    pub stack_trace: Vec<StackFrame>,
+}
+
+impl ErrorRequest {
+    pub fn new(error_type: &str, error_message: &str) -> Self {
+        Self {
+            error_type: error_type.to_string(),
+            error_message: error_message.to_string(),
+            stack_trace: Vec::new(),
+        }
+    }
+
+    /// Creates an error request from an error value, using the name of its type as the error type.
+    ///
+    /// The name is stripped of its module path and generic arguments, e.g. `std::io::Error` is reported as `Error`
+    /// and `&alloc::string::String` as `String`.
+    pub fn from_error<E: Display>(err: &E) -> Self {
+        Self::new(type_name::<E>(), &err.to_string())
+    }
+
+    /// Sets the stack trace to the frames of `backtrace`. Has no effect if the backtrace wasn't captured.
+    pub fn with_backtrace(mut self, backtrace: &Backtrace) -> Self {
+        if backtrace.status() == BacktraceStatus::Captured {
+            self.stack_trace = parse_backtrace(&backtrace.to_string());
+        }
+        self
+    }
+
+    /// Captures a backtrace of the current thread and sets it as the stack trace.
+    /// Call it where the error is created, the frames of the caller are reported.
+    ///
+    /// Like [`Backtrace::capture`], this only has an effect if the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` env-vars are set.
+    pub fn capture_backtrace(self) -> Self {
+        self.with_backtrace(&Backtrace::capture())
+    }
+
+    /// Formats the error as an ErrorRequest JSON.
+    pub fn to_json(&self) -> String {
+        json!({
+            "errorMessage": self.error_message,
+            "errorType": self.error_type,
+            "stackTrace": self.stack_trace.iter().map(|f| f.to_string()).collect::<Vec<_>>(),
+        })
+        .to_string()
+    }
+
+    /// Formats the error as an X-Ray error cause JSON, the value of the `Lambda-Runtime-Function-XRay-Error-Cause` header.
+    ///
+    /// Since header values are limited to ASCII, other characters are escaped as `\uXXXX` sequences.
+    /// The outermost stack frames are dropped as needed to fit the cause within [`MAX_XRAY_ERROR_CAUSE_SIZE`].
+    pub fn xray_error_cause(&self) -> String {
+        let working_directory = std::env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let cause = self.render_xray_error_cause(&working_directory, &self.stack_trace);
+        if cause.len() <= MAX_XRAY_ERROR_CAUSE_SIZE {
+            return cause;
+        }
+        // Keep the innermost frames that fit, counting a separating comma for every frame and new path.
+        let mut size = self.render_xray_error_cause(&working_directory, &[]).len();
+        let mut paths: Vec<&str> = Vec::new();
+        let mut frames = 0;
+        for frame in &self.stack_trace {
+            size += escape_non_ascii(&frame_json(frame).to_string()).len() + 1;
+            if let Some(path) = frame.path.as_deref().filter(|path| !paths.contains(path)) {
+                size += escape_non_ascii(&Value::from(path).to_string()).len() + 1;
+                paths.push(path);
+            }
+            if size > MAX_XRAY_ERROR_CAUSE_SIZE {
+                break;
+            }
+            frames += 1;
+        }
+        self.render_xray_error_cause(&working_directory, &self.stack_trace[..frames])
+    }
+
+    fn render_xray_error_cause(
+        &self,
+        working_directory: &str,
+        stack_trace: &[StackFrame],
+    ) -> String {
+        let mut paths: Vec<&str> = Vec::new();
+        for path in stack_trace.iter().filter_map(|f| f.path.as_deref()) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        let stack: Vec<Value> = stack_trace.iter().map(frame_json).collect();
+
+        let cause = json!({
+            "working_directory": working_directory,
+            "paths": paths,
+            "exceptions": [{
+                "type": self.error_type,
+                "message": self.error_message,
+                "stack": stack,
+            }],
+        })
+        .to_string();
+        escape_non_ascii(&cause)
+    }
+}
+
+impl Display for ErrorRequest {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", &self.error_type, &self.error_message)
+    }
+}
+
+/// Returns the name of `T` without its module path, generic arguments and leading references.
+fn type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    let name = name.trim_start_matches('&');
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Formats a stack frame as an entry of the `stack` of an X-Ray exception.
+fn frame_json(frame: &StackFrame) -> Value {
+    json!({
+        "label": frame.label,
+        "path": frame.path.as_deref().unwrap_or(""),
+        "line": frame.line.unwrap_or(0),
+    })
+}
+
+/// Escapes the non-ASCII characters of a JSON document as `\uXXXX` sequences of UTF-16 code units.
+/// Such characters only appear within strings, where the escapes are decoded back to the same characters.
+fn escape_non_ascii(json: &str) -> String {
+    if json.is_ascii() {
+        return json.to_string();
+    }
+    let mut escaped = String::with_capacity(json.len() + 16);
+    for c in json.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    escaped
+}
+
+/// Parses the frames of a rendered [`Backtrace`], up to the program's entry point. Frames look like:
+///
+/// ```text
+///    1: crate::module::function
+///              at ./src/module.rs:4:30
+/// ```
+fn parse_backtrace(rendered: &str) -> Vec<StackFrame> {
+    let mut frames: Vec<StackFrame> = Vec::new();
+    for line in rendered.lines().map(str::trim) {
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some(frame) = frames.last_mut() {
+                // The location is `path:line:column`, and the path itself may contain colons.
+                let mut parts = location.rsplitn(3, ':');
+                let _column = parts.next();
+                let line = parts.next().and_then(|l| l.parse().ok());
+                match (line, parts.next()) {
+                    (Some(line), Some(path)) => {
+                        frame.path = Some(path.to_string());
+                        frame.line = Some(line);
+                    }
+                    _ => frame.path = Some(location.to_string()),
+                }
+            }
+        } else if let Some((index, label)) = line.split_once(": ") {
+            if !index.bytes().all(|b| b.is_ascii_digit()) {
+                continue;
+            }
+            // Like short backtraces, stop at the frames of the program's startup code.
+            if label.contains("__rust_begin_short_backtrace") {
+                break;
+            }
+            // Skip the frames of capturing the backtrace itself.
+            if label.starts_with("std::backtrace") || label.starts_with("rtlambda::error::") {
+                frames.clear();
+            } else {
+                frames.push(StackFrame {
+                    label: label.to_string(),
+                    path: None,
+                    line: None,
+                });
+            }
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rendered_backtraces() {
+        let rendered = "   0: std::backtrace_rs::backtrace::libunwind::trace
+             at /rustc/library/std/src/../../backtrace/src/backtrace/libunwind.rs:116:5
+   1: std::backtrace::Backtrace::create
+             at /rustc/library/std/src/backtrace.rs:331:13
+   2: rtlambda::error::ErrorRequest::capture_backtrace
+             at ./src/error.rs:104:28
+   3: orders::handle
+             at ./src/main.rs:42:13
+   4: orders::windows::handle
+             at C:\\orders\\src\\main.rs:7:9
+   5: <alloc::boxed::Box<F,A> as core::ops::function::Fn<Args>>::call
+   6: orders::inline
+             at ./src/inline.rs
+   7: std::sys::backtrace::__rust_begin_short_backtrace
+             at /rustc/library/std/src/sys/backtrace.rs:152:18
+   8: main
+";
+        let frame = |label: &str, path: Option<&str>, line: Option<u32>| StackFrame {
+            label: label.to_string(),
+            path: path.map(str::to_string),
+            line,
+        };
+        assert_eq!(
+            parse_backtrace(rendered),
+            [
+                frame("orders::handle", Some("./src/main.rs"), Some(42)),
+                frame(
+                    "orders::windows::handle",
+                    Some("C:\\orders\\src\\main.rs"),
+                    Some(7)
+                ),
+                frame(
+                    "<alloc::boxed::Box<F,A> as core::ops::function::Fn<Args>>::call",
+                    None,
+                    None
+                ),
+                frame("orders::inline", Some("./src/inline.rs"), None),
+            ]
+        );
+        assert_eq!(parse_backtrace("disabled backtrace"), []);
+    }
+
+    #[test]
+    fn reports_the_error_type_name_without_its_path() {
+        let io_error = std::io::Error::other("failed");
+        assert_eq!(ErrorRequest::from_error(&io_error).error_type, "Error");
+        assert_eq!(
+            ErrorRequest::from_error(&"failed".to_string()).error_type,
+            "String"
+        );
+        assert_eq!(ErrorRequest::from_error(&&"failed").error_type, "str");
+        let boxed: Box<dyn std::error::Error> = "failed".into();
+        assert_eq!(ErrorRequest::from_error(&boxed).error_type, "Box");
+    }
+
+    #[test]
+    fn escapes_non_ascii_characters_in_the_xray_error_cause() {
+        let error = ErrorRequest::new("Error", "Ünïcode failure 😱");
+        let cause = error.xray_error_cause();
+
+        assert!(cause.is_ascii());
+        assert!(cause.contains("\\ud83d\\ude31"));
+        let parsed: Value = serde_json::from_str(&cause).unwrap();
+        assert_eq!(
+            parsed["exceptions"][0]["message"],
+            "Ünïcode failure 😱".to_string()
+        );
+    }
+
+    #[test]
+    fn caps_the_size_of_the_xray_error_cause() {
+        let mut error = ErrorRequest::new("Error", "Too deep");
+        error.stack_trace = (0..6_000)
+            .map(|i| StackFrame {
+                label: format!("recurse_{:0>128}", i),
+                path: Some("src/main.rs".to_string()),
+                line: Some(i),
+            })
+            .collect();
+        let cause = error.xray_error_cause();
+
+        assert!(cause.len() <= MAX_XRAY_ERROR_CAUSE_SIZE);
+        let parsed: Value = serde_json::from_str(&cause).unwrap();
+        let stack = parsed["exceptions"][0]["stack"].as_array().unwrap();
+        assert!(!stack.is_empty() && stack.len() < 6_000);
+        assert_eq!(stack[0]["label"], "recurse_".to_string() + &"0".repeat(128));
+    }
+}
@@ -0,0 +1,716 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::data::context::RefLambdaContext;
+use crate::data::env::RuntimeEnvVars;
+use crate::data::response::LambdaAPIResponse;
+use crate::error::Error;
+use crate::runtime::stats::time_deserialize;
+use crate::runtime::EventHandler;
+
+use ::http::header::{HeaderName, HeaderValue, COOKIE, HOST, SET_COOKIE};
+use ::http::{HeaderMap, Method, Request, Response};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Map, Value};
+
+/// The body of an HTTP request or response passed through the adapter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Body {
+    #[default]
+    Empty,
+    /// A UTF-8 body, sent to and from AWS as is.
+    Text(String),
+    /// A binary body, sent to and from AWS as a base64 encoded string.
+    Binary(Vec<u8>),
+}
+
+impl Body {
+    /// Returns the contents of the body as a byte slice.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Empty => &[],
+            Self::Text(text) => text.as_bytes(),
+            Self::Binary(data) => data,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.as_bytes().is_empty()
+    }
+}
+
+impl From<()> for Body {
+    fn from(_: ()) -> Self {
+        Self::Empty
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(data: Vec<u8>) -> Self {
+        Self::Binary(data)
+    }
+}
+
+/// The AWS service integration that produced an HTTP event.
+///
+/// Inserted into the extensions of every converted [`Request`], and used to format the matching response shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOrigin {
+    /// API Gateway REST API (payload format 1.0).
+    ApiGatewayV1,
+    /// API Gateway HTTP API (payload format 2.0).
+    ApiGatewayV2,
+    /// Application Load Balancer. `multi_value_headers` is set when the target group has multi-value headers enabled.
+    Alb { multi_value_headers: bool },
+    /// Lambda function URL (payload format 2.0).
+    FunctionUrl,
+    /// API Gateway WebSocket API.
+    WebSocket,
+}
+
+/// The decoded query string parameters of a request, in the order they were received.
+///
+/// Inserted into the extensions of every converted [`Request`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryStringParameters(pub Vec<(String, String)>);
+
+impl QueryStringParameters {
+    /// Returns the first value of the parameter `name`.
+    pub fn first(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns an iterator over all values of the parameter `name`.
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The `requestContext` object of the original event, inserted into the extensions of every converted [`Request`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestContext(pub Value);
+
+/// Wraps an HTTP handler in an [`EventHandler`] that can be returned from the initializer of a [`crate::runtime::DefaultRuntime`].
+///
+/// Each event is converted into an [`http::Request`](::http::Request) using [`into_request`],
+/// and the handler's [`http::Response`](::http::Response) is converted back into the shape matching the event's origin using [`from_response`].
+pub fn http_handler<F, ENV, R>(handler: F) -> EventHandler<ENV, R, Value, Error>
+where
+    F: Fn(Request<Body>, RefLambdaContext<ENV, R>) -> Response<Body> + 'static,
+    ENV: RuntimeEnvVars + 'static,
+    R: LambdaAPIResponse + 'static,
+{
+    Box::new(move |event, context| {
+        let event = event.ok_or_else(|| Error::new("Missing event body".to_string()))?;
+        let request = into_request(event)?;
+        let origin = *request
+            .extensions()
+            .get::<RequestOrigin>()
+            .expect("into_request always sets the request origin");
+        Ok(from_response(origin, handler(request, context)))
+    })
+}
+
+/// Detects the shape of an HTTP event JSON and converts it into an [`http::Request`](::http::Request).
+///
+/// The [`RequestOrigin`], [`QueryStringParameters`] and [`RequestContext`] of the event are inserted into the request's extensions.
+pub fn into_request(event: &str) -> Result<Request<Body>, Error> {
+    let event: Value = time_deserialize(|| serde_json::from_str(event))
+        .map_err(|err| Error::new(format!("Failed deserializing event from JSON. {}", err)))?;
+    let context = event.get("requestContext").cloned().unwrap_or(Value::Null);
+
+    let origin = detect_origin(&event, &context)
+        .ok_or_else(|| Error::new("Unrecognized HTTP event shape".to_string()))?;
+
+    let mut headers = HeaderMap::new();
+    let (method, path, query) = match origin {
+        RequestOrigin::ApiGatewayV2 | RequestOrigin::FunctionUrl => {
+            append_headers(&mut headers, event.get("headers"))?;
+            if let Some(Value::Array(cookies)) = event.get("cookies") {
+                let cookies: Vec<&str> = cookies.iter().filter_map(Value::as_str).collect();
+                if !cookies.is_empty() {
+                    headers.insert(COOKIE, header_value(&cookies.join("; "))?);
+                }
+            }
+            let method = str_at(&context, &["http", "method"]).unwrap_or("GET");
+            let path = encode_path(str_at(&event, &["rawPath"]).unwrap_or("/"), false);
+            let query = parse_query(str_at(&event, &["rawQueryString"]).unwrap_or(""));
+            (method, path, query)
+        }
+        RequestOrigin::ApiGatewayV1 | RequestOrigin::Alb { .. } | RequestOrigin::WebSocket => {
+            match event.get("multiValueHeaders") {
+                Some(Value::Object(_)) => {
+                    append_headers(&mut headers, event.get("multiValueHeaders"))?
+                }
+                _ => append_headers(&mut headers, event.get("headers"))?,
+            };
+            // WebSocket events carry no HTTP method, treat them as GET requests.
+            let method = str_at(&event, &["httpMethod"]).unwrap_or("GET");
+            // REST and WebSocket APIs decode the path, so a literal `%` is encoded rather than read as an escape.
+            // ALB forwards the path as it was received, keeping its escapes.
+            let decoded = !matches!(origin, RequestOrigin::Alb { .. });
+            let path = encode_path(str_at(&event, &["path"]).unwrap_or("/"), decoded);
+            let params = match event.get("multiValueQueryStringParameters") {
+                Some(Value::Object(params)) => Some(params),
+                _ => match event.get("queryStringParameters") {
+                    Some(Value::Object(params)) => Some(params),
+                    _ => None,
+                },
+            };
+            let mut query = Vec::new();
+            for (key, values) in params.into_iter().flatten() {
+                for value in values_of(values) {
+                    query.push((key.clone(), value.to_string()));
+                }
+            }
+            // ALB does not decode query string parameters before passing them to the function.
+            if let RequestOrigin::Alb { .. } = origin {
+                query = query
+                    .into_iter()
+                    .map(|(k, v)| (percent_decode(&k), percent_decode(&v)))
+                    .collect();
+            }
+            (method, path, query)
+        }
+    };
+
+    let mut uri = match headers.get(HOST).and_then(|v| v.to_str().ok()) {
+        Some(host) => {
+            let scheme = headers
+                .get("x-forwarded-proto")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("https");
+            format!("{}://{}{}", scheme, host, path)
+        }
+        None => path,
+    };
+    if !query.is_empty() {
+        let encoded: Vec<String> = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", percent_encode(k), percent_encode(v)))
+            .collect();
+        uri.push('?');
+        uri.push_str(&encoded.join("&"));
+    }
+
+    let body =
+        match event.get("body") {
+            Some(Value::String(body)) => {
+                if event.get("isBase64Encoded").and_then(Value::as_bool) == Some(true) {
+                    Body::Binary(BASE64.decode(body).map_err(|err| {
+                        Error::new(format!("Failed decoding base64 body. {}", err))
+                    })?)
+                } else {
+                    Body::Text(body.clone())
+                }
+            }
+            _ => Body::Empty,
+        };
+
+    let method = Method::from_bytes(method.as_bytes())
+        .map_err(|err| Error::new(format!("Invalid HTTP method. {}", err)))?;
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(body)
+        .map_err(|err| Error::new(format!("Failed building HTTP request. {}", err)))?;
+    *request.headers_mut() = headers;
+    request.extensions_mut().insert(origin);
+    request
+        .extensions_mut()
+        .insert(QueryStringParameters(query));
+    request.extensions_mut().insert(RequestContext(context));
+
+    Ok(request)
+}
+
+/// Converts an [`http::Response`](::http::Response) into the response JSON expected by the integration identified by `origin`.
+pub fn from_response(origin: RequestOrigin, response: Response<Body>) -> Value {
+    let (parts, body) = response.into_parts();
+    let status = parts.status;
+
+    let mut output = Map::new();
+    output.insert("statusCode".to_string(), json!(status.as_u16()));
+    let (body, is_base64) = match body {
+        Body::Empty => (String::new(), false),
+        Body::Text(text) => (text, false),
+        Body::Binary(data) => (BASE64.encode(data), true),
+    };
+    output.insert("body".to_string(), json!(body));
+    output.insert("isBase64Encoded".to_string(), json!(is_base64));
+
+    match origin {
+        RequestOrigin::ApiGatewayV2 | RequestOrigin::FunctionUrl => {
+            // Payload format 2.0 joins multiple header values with commas and returns cookies separately.
+            let mut headers = Map::new();
+            for name in parts.headers.keys() {
+                if name == SET_COOKIE {
+                    continue;
+                }
+                let values: Vec<&str> = header_strs(&parts.headers, name).collect();
+                headers.insert(name.to_string(), json!(values.join(",")));
+            }
+            let cookies: Vec<&str> = header_strs(&parts.headers, &SET_COOKIE).collect();
+            output.insert("headers".to_string(), Value::Object(headers));
+            output.insert("cookies".to_string(), json!(cookies));
+        }
+        RequestOrigin::ApiGatewayV1 | RequestOrigin::WebSocket => {
+            output.insert("headers".to_string(), single_value_headers(&parts.headers));
+            output.insert(
+                "multiValueHeaders".to_string(),
+                multi_value_headers(&parts.headers),
+            );
+        }
+        RequestOrigin::Alb {
+            multi_value_headers: multi,
+        } => {
+            output.insert(
+                "statusDescription".to_string(),
+                json!(format!(
+                    "{} {}",
+                    status.as_u16(),
+                    status.canonical_reason().unwrap_or("")
+                )),
+            );
+            // ALB rejects responses that don't match the target group's multi-value headers setting.
+            if multi {
+                output.insert(
+                    "multiValueHeaders".to_string(),
+                    multi_value_headers(&parts.headers),
+                );
+            } else {
+                output.insert("headers".to_string(), single_value_headers(&parts.headers));
+            }
+        }
+    };
+
+    Value::Object(output)
+}
+
+fn detect_origin(event: &Value, context: &Value) -> Option<RequestOrigin> {
+    if context.get("elb").is_some() {
+        return Some(RequestOrigin::Alb {
+            multi_value_headers: matches!(event.get("multiValueHeaders"), Some(Value::Object(_))),
+        });
+    }
+    if context.get("connectionId").is_some() && context.get("eventType").is_some() {
+        return Some(RequestOrigin::WebSocket);
+    }
+    if event.get("version").and_then(Value::as_str) == Some("2.0") {
+        let is_url = str_at(context, &["domainName"])
+            .map(|domain| domain.contains(".lambda-url."))
+            .unwrap_or(false);
+        return Some(if is_url {
+            RequestOrigin::FunctionUrl
+        } else {
+            RequestOrigin::ApiGatewayV2
+        });
+    }
+    if event.get("httpMethod").is_some() {
+        return Some(RequestOrigin::ApiGatewayV1);
+    }
+    None
+}
+
+fn str_at<'a>(value: &'a Value, path: &[&str]) -> Option<&'a str> {
+    path.iter()
+        .try_fold(value, |value, key| value.get(key))
+        .and_then(Value::as_str)
+}
+
+/// Returns the string values of a JSON value that is either a string or an array of strings.
+fn values_of(value: &Value) -> Vec<&str> {
+    match value {
+        Value::String(v) => vec![v.as_str()],
+        Value::Array(values) => values.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value).map_err(|err| Error::new(format!("Invalid header value. {}", err)))
+}
+
+fn append_headers(headers: &mut HeaderMap, source: Option<&Value>) -> Result<(), Error> {
+    if let Some(Value::Object(source)) = source {
+        for (name, values) in source {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| Error::new(format!("Invalid header name. {}", err)))?;
+            for value in values_of(values) {
+                headers.append(name.clone(), header_value(value)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn header_strs<'a>(headers: &'a HeaderMap, name: &HeaderName) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+}
+
+fn single_value_headers(headers: &HeaderMap) -> Value {
+    let mut output = Map::new();
+    for name in headers.keys() {
+        if let Some(value) = header_strs(headers, name).last() {
+            output.insert(name.to_string(), json!(value));
+        }
+    }
+    Value::Object(output)
+}
+
+fn multi_value_headers(headers: &HeaderMap) -> Value {
+    let mut output = Map::new();
+    for name in headers.keys() {
+        let values: Vec<&str> = header_strs(headers, name).collect();
+        output.insert(name.to_string(), json!(values));
+    }
+    Value::Object(output)
+}
+
+/// Parses a raw (percent-encoded) query string into decoded key-value pairs.
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((k, v)) => (percent_decode(k), percent_decode(v)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// Decodes a `application/x-www-form-urlencoded` query string component. Invalid escapes are kept as is.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => output.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2]))
+            {
+                (Some(high), Some(low)) => {
+                    output.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => output.push(b'%'),
+            },
+            byte => output.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Percent-encodes the characters that are not allowed in a URI path.
+/// Existing escapes are kept as is, unless `decoded` is set - in which case `%` is encoded as well.
+fn encode_path(path: &str, decoded: bool) -> String {
+    let mut output = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => output.push(byte as char),
+            b'%' if !decoded => output.push('%'),
+            b'-' | b'.' | b'_' | b'~' | b'/' | b':' | b'@' | b'!' | b'$' | b'&' | b'\'' => {
+                output.push(byte as char)
+            }
+            b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => output.push(byte as char),
+            _ => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|v| v as u8)
+}
+
+/// Percent-encodes a query string component, leaving only unreserved characters as is.
+fn percent_encode(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                output.push(byte as char)
+            }
+            _ => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_api_gateway_v1_event() {
+        let event = json!({
+            "resource": "/{proxy+}",
+            "path": "/files/100%/a b",
+            "httpMethod": "POST",
+            "headers": {"Host": "api.example.com", "Accept": "text/html"},
+            "multiValueHeaders": {
+                "Host": ["api.example.com"],
+                "Accept": ["text/html", "application/json"],
+                "X-Forwarded-Proto": ["http"]
+            },
+            "queryStringParameters": {"q": "a&b"},
+            "multiValueQueryStringParameters": {"q": ["a&b", "c d"]},
+            "requestContext": {"stage": "prod", "requestId": "id"},
+            "body": "aGVsbG8=",
+            "isBase64Encoded": true
+        });
+        let request = into_request(&event.to_string()).unwrap();
+
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(
+            request.uri(),
+            "http://api.example.com/files/100%25/a%20b?q=a%26b&q=c%20d"
+        );
+        let accept: Vec<_> = request.headers().get_all("accept").iter().collect();
+        assert_eq!(accept, ["text/html", "application/json"]);
+        assert_eq!(request.body(), &Body::Binary(b"hello".to_vec()));
+
+        let extensions = request.extensions();
+        assert_eq!(
+            extensions.get::<RequestOrigin>(),
+            Some(&RequestOrigin::ApiGatewayV1)
+        );
+        let query = extensions.get::<QueryStringParameters>().unwrap();
+        assert_eq!(query.all("q").collect::<Vec<_>>(), ["a&b", "c d"]);
+        assert_eq!(
+            extensions.get::<RequestContext>().unwrap().0["stage"],
+            json!("prod")
+        );
+    }
+
+    #[test]
+    fn converts_api_gateway_v2_event() {
+        let event = json!({
+            "version": "2.0",
+            "rawPath": "/a%2Fb/c",
+            "rawQueryString": "name=J%C3%BCrgen&empty&x=1+2",
+            "cookies": ["a=1", "b=2"],
+            "headers": {"host": "id.execute-api.us-east-1.amazonaws.com", "accept": "*/*"},
+            "requestContext": {
+                "domainName": "id.execute-api.us-east-1.amazonaws.com",
+                "http": {"method": "PUT"}
+            },
+            "body": "{\"key\":\"value\"}",
+            "isBase64Encoded": false
+        });
+        let request = into_request(&event.to_string()).unwrap();
+
+        assert_eq!(request.method(), Method::PUT);
+        assert_eq!(
+            request.uri(),
+            "https://id.execute-api.us-east-1.amazonaws.com/a%2Fb/c?name=J%C3%BCrgen&empty=&x=1%202"
+        );
+        assert_eq!(request.headers()[COOKIE], "a=1; b=2");
+        assert_eq!(
+            request.body(),
+            &Body::Text("{\"key\":\"value\"}".to_string())
+        );
+        assert_eq!(
+            request.extensions().get::<RequestOrigin>(),
+            Some(&RequestOrigin::ApiGatewayV2)
+        );
+        let query = request.extensions().get::<QueryStringParameters>().unwrap();
+        assert_eq!(query.first("name"), Some("Jürgen"));
+        assert_eq!(query.first("empty"), Some(""));
+        assert_eq!(query.first("x"), Some("1 2"));
+
+        let response = Response::builder()
+            .status(201)
+            .header("content-type", "text/plain")
+            .header("x-values", "1")
+            .header("x-values", "2")
+            .header(SET_COOKIE, "a=1")
+            .header(SET_COOKIE, "b=2")
+            .body(Body::from("created"))
+            .unwrap();
+        assert_eq!(
+            from_response(RequestOrigin::ApiGatewayV2, response),
+            json!({
+                "statusCode": 201,
+                "body": "created",
+                "isBase64Encoded": false,
+                "headers": {"content-type": "text/plain", "x-values": "1,2"},
+                "cookies": ["a=1", "b=2"]
+            })
+        );
+    }
+
+    #[test]
+    fn converts_alb_event() {
+        let event = json!({
+            "requestContext": {"elb": {"targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123:targetgroup/tg/1"}},
+            "httpMethod": "GET",
+            "path": "/a%2Fb",
+            "multiValueQueryStringParameters": {"q": ["a%20b", "c+d"], "k%3D": ["v"]},
+            "multiValueHeaders": {
+                "host": ["lb.example.com"],
+                "x-forwarded-proto": ["https"],
+                "cookie": ["a=1", "b=2"]
+            },
+            "body": "",
+            "isBase64Encoded": false
+        });
+        let request = into_request(&event.to_string()).unwrap();
+
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(
+            request.uri(),
+            "https://lb.example.com/a%2Fb?k%3D=v&q=a%20b&q=c%20d"
+        );
+        let cookies: Vec<_> = request.headers().get_all(COOKIE).iter().collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+        assert_eq!(request.body(), &Body::Text(String::new()));
+        assert_eq!(
+            request.extensions().get::<RequestOrigin>(),
+            Some(&RequestOrigin::Alb {
+                multi_value_headers: true
+            })
+        );
+
+        let response = Response::builder()
+            .status(404)
+            .header("x-values", "1")
+            .header("x-values", "2")
+            .body(Body::Binary(vec![0, 1, 2]))
+            .unwrap();
+        assert_eq!(
+            from_response(
+                RequestOrigin::Alb {
+                    multi_value_headers: true
+                },
+                response
+            ),
+            json!({
+                "statusCode": 404,
+                "statusDescription": "404 Not Found",
+                "body": "AAEC",
+                "isBase64Encoded": true,
+                "multiValueHeaders": {"x-values": ["1", "2"]}
+            })
+        );
+    }
+
+    #[test]
+    fn converts_function_url_event() {
+        let event = json!({
+            "version": "2.0",
+            "rawPath": "/items/a%20b",
+            "rawQueryString": "",
+            "headers": {"host": "abc.lambda-url.us-east-1.on.aws", "x-values": "1,2"},
+            "requestContext": {
+                "domainName": "abc.lambda-url.us-east-1.on.aws",
+                "http": {"method": "DELETE"}
+            },
+            "isBase64Encoded": false
+        });
+        let request = into_request(&event.to_string()).unwrap();
+
+        assert_eq!(request.method(), Method::DELETE);
+        assert_eq!(
+            request.uri(),
+            "https://abc.lambda-url.us-east-1.on.aws/items/a%20b"
+        );
+        assert_eq!(request.headers()["x-values"], "1,2");
+        assert_eq!(request.body(), &Body::Empty);
+        assert_eq!(
+            request.extensions().get::<RequestOrigin>(),
+            Some(&RequestOrigin::FunctionUrl)
+        );
+
+        let response = Response::builder()
+            .status(204)
+            .header(SET_COOKIE, "a=1")
+            .body(Body::Empty)
+            .unwrap();
+        assert_eq!(
+            from_response(RequestOrigin::FunctionUrl, response),
+            json!({
+                "statusCode": 204,
+                "body": "",
+                "isBase64Encoded": false,
+                "headers": {},
+                "cookies": ["a=1"]
+            })
+        );
+    }
+
+    #[test]
+    fn converts_websocket_event() {
+        let event = json!({
+            "requestContext": {
+                "routeKey": "sendMessage",
+                "eventType": "MESSAGE",
+                "connectionId": "L0SM9cOFvHcCIhw=",
+                "domainName": "ws.example.com",
+                "stage": "prod"
+            },
+            "headers": {"Host": "ws.example.com"},
+            "body": "{\"action\":\"sendMessage\"}",
+            "isBase64Encoded": false
+        });
+        let request = into_request(&event.to_string()).unwrap();
+
+        // WebSocket events carry neither a method nor a path.
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(request.uri(), "https://ws.example.com/");
+        assert_eq!(
+            request.body(),
+            &Body::Text("{\"action\":\"sendMessage\"}".to_string())
+        );
+        assert_eq!(
+            request.extensions().get::<RequestOrigin>(),
+            Some(&RequestOrigin::WebSocket)
+        );
+        assert_eq!(
+            request.extensions().get::<RequestContext>().unwrap().0["connectionId"],
+            json!("L0SM9cOFvHcCIhw=")
+        );
+
+        let response = Response::builder()
+            .status(200)
+            .header("x-values", "1")
+            .header("x-values", "2")
+            .body(Body::from("sent"))
+            .unwrap();
+        assert_eq!(
+            from_response(RequestOrigin::WebSocket, response),
+            json!({
+                "statusCode": 200,
+                "body": "sent",
+                "isBase64Encoded": false,
+                "headers": {"x-values": "2"},
+                "multiValueHeaders": {"x-values": ["1", "2"]}
+            })
+        );
+    }
+}
@@ -4,37 +4,152 @@
 
 /// Implementations of the `rtlambda` API for different HTTP backends.
 pub mod backends;
+/// Loads typed configuration structs from env-vars.
+pub mod config;
+/// Provides the AWS credentials of the function, refreshing them before they expire.
+pub mod credentials;
 /// A collection of traits and default implementations for them, representing the library's core data structures.
 pub mod data;
 /// Defines error types and constants.
 pub mod error;
+#[cfg(feature = "http")]
+/// An adapter for writing event handlers for HTTP events using the [http](https://crates.io/crates/http) crate types.
+pub mod http;
+#[cfg(feature = "log")]
+/// A [log](https://crates.io/crates/log) backend honoring the log format and level configured for the function.
+pub mod log;
+#[cfg(feature = "metrics")]
+/// Records metrics in the CloudWatch Embedded Metric Format.
+pub mod metrics;
+#[cfg(feature = "opentelemetry")]
+/// Records an [OpenTelemetry](https://opentelemetry.io) span for each invocation.
+pub mod opentelemetry;
+/// Dispatches events to handlers by the source detected from their shape.
+pub mod router;
 /// Defines the [`crate::runtime::LambdaRuntime`] API and provides a default generic implementation.
 pub mod runtime;
+#[cfg(feature = "sigv4")]
+/// Signs requests to AWS APIs using Signature Version 4.
+pub mod sigv4;
+#[cfg(feature = "tower")]
+/// Adapters for running [tower](https://crates.io/crates/tower) services as event handlers and vice versa.
+pub mod tower;
 /// Defines the [`crate::transport::Transport`] abstraction used to support multiple HTTP backends.
 pub mod transport;
+#[cfg(feature = "xray")]
+/// Records X-Ray subsegments and sends them to the X-Ray daemon.
+pub mod xray;
 
 /// The current Lambda API version used on AWS.
 pub static LAMBDA_VER: &str = "2018-06-01";
 
+/// Generates a `main` function that runs the annotated event handler on the default runtime, created with the ureq backend.
+///
+/// The `OUT` and `ERR` types of the runtime are taken from the handler's `Result<OUT, ERR>` return type.
+/// The handler accepts the event and optionally the context object - a [`data::context::RefLambdaContext`],
+/// or an [`data::context::OwnedLambdaContext`] which selects [`data::context::OwnedContextKind`].
+/// Events are deserialized from JSON into the type of the first argument, converting errors into `ERR` using `From<error::Error>`,
+/// unless it is `Option<&str>` - which is passed the event as is.
+///
+/// An init function, accepting the [`runtime::init::InitContext`] and returning `Result<S, ERR>`, may be set using
+/// `#[rtlambda::main(init = my_init)]`. It is run once during initialization, and a reference to the state `S` it returns
+/// is passed to the handler after the event.
+///
+/// The handler may be an `async fn`, in which case each invocation is driven to completion using [`runtime::entry::block_on`].
+/// This requires the `tokio` feature, providing the reactor and timers most async code depends on.
+/// The generated `main` uses the ureq backend, so the `ureq` feature is required as well.
+///
+#[cfg_attr(feature = "tokio", doc = "```no_run")]
+#[cfg_attr(not(feature = "tokio"), doc = "```ignore")]
+/// # use rtlambda::prelude::*;
+/// # use serde::{Deserialize, Serialize};
+/// # #[derive(Deserialize)]
+/// # struct Order { id: u64 }
+/// # #[derive(Serialize)]
+/// # struct Receipt { id: u64, request_id: String }
+/// # struct Db;
+/// # impl Db {
+/// #     async fn insert(&self, order: Order, request_id: &str) -> Result<Receipt, String> {
+/// #         Ok(Receipt { id: order.id, request_id: request_id.to_string() })
+/// #     }
+/// # }
+/// # fn connect(_init: &mut InitContext<LambdaRuntimeEnv>) -> Result<Db, String> {
+/// #     Ok(Db)
+/// # }
+/// #[rtlambda::main(init = connect)]
+/// async fn handler(
+///     event: Order,
+///     db: &Db,
+///     context: RefLambdaContext<'_, LambdaRuntimeEnv, UreqResponse>,
+/// ) -> Result<Receipt, String> {
+///     db.insert(event, context.aws_request_id().unwrap()).await
+/// }
+/// ```
+#[cfg(feature = "macros")]
+pub use rtlambda_derive::main;
+
+/// Checked by the `main` generated by [`main`], which runs the handler on the ureq backend.
+#[cfg(all(feature = "macros", feature = "ureq"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __require_ureq {
+    () => {};
+}
+
+#[cfg(all(feature = "macros", not(feature = "ureq")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __require_ureq {
+    () => {
+        ::core::compile_error!("#[rtlambda::main] requires the `ureq` feature of rtlambda");
+    };
+}
+
+/// Checked by the `main` generated by [`main`] for `async` handlers.
+#[cfg(all(feature = "macros", feature = "tokio"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __require_tokio {
+    () => {};
+}
+
+#[cfg(all(feature = "macros", not(feature = "tokio")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __require_tokio {
+    () => {
+        ::core::compile_error!("`async` event handlers require the `tokio` feature of rtlambda");
+    };
+}
+
 /// A prelude that contains all the relevant imports when using the library's default runtime implementation,
 /// which currently ships with a [ureq](https://crates.io/crates/ureq) based HTTP Backend and [serde_json](https://crates.io/crates/serde_json) for serialization.
 pub mod prelude {
     pub use crate::backends::ureq::*;
-    pub use crate::data::context::{LambdaContext, RefLambdaContext};
+    pub use crate::data::context::{
+        LambdaContext, OwnedContextKind, OwnedLambdaContext, RefContextKind, RefLambdaContext,
+    };
     pub use crate::data::env::LambdaRuntimeEnv;
-    pub use crate::runtime::{DefaultRuntime, LambdaRuntime};
+    pub use crate::runtime::init::InitContext;
+    pub use crate::runtime::registry::{HandlerOutput, HandlerRegistry};
+    pub use crate::runtime::{DefaultRuntime, EventHandler, LambdaRuntime, OwnedEventHandler};
     pub use crate::LAMBDA_VER;
 }
 
 /// Creates a [`crate::runtime::DefaultRuntime`] with the given response, transport, env, out, err types as well as version and initializer.
+/// An optional context kind type - see [`crate::data::context::ContextKind`] - may be given after the err type.
 #[macro_export]
 macro_rules! create_runtime {
     ($response:ty, $transport:ty, $env:ty, $out:ty, $err:ty, $ver:expr, $init:ident) => {
         DefaultRuntime::<$response, $transport, $env, $out, $err>::new($ver, $init);
     };
+    ($response:ty, $transport:ty, $env:ty, $out:ty, $err:ty, $ctx:ty, $ver:expr, $init:ident) => {
+        DefaultRuntime::<$response, $transport, $env, $out, $err, $ctx>::new($ver, $init);
+    };
 }
 
 /// Creates a [`crate::runtime::DefaultRuntime`] with ureq based HTTP backend and the default implementation of env-vars handling.
+/// An optional context kind type - see [`crate::data::context::ContextKind`] - may be given after the err type.
 #[macro_export]
 macro_rules! default_runtime {
     ($out:ty, $err:ty, $ver:expr, $init:ident) => {
@@ -48,4 +163,16 @@ macro_rules! default_runtime {
             $init
         )
     };
+    ($out:ty, $err:ty, $ctx:ty, $ver:expr, $init:ident) => {
+        create_runtime!(
+            UreqResponse,
+            UreqTransport,
+            LambdaRuntimeEnv,
+            $out,
+            $err,
+            $ctx,
+            $ver,
+            $init
+        )
+    };
 }
@@ -0,0 +1,226 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::data::env::{LambdaRuntimeEnv, LogFormat, LogLevel, RuntimeEnvVars};
+use crate::data::time::civil_from_days;
+use crate::error::Error;
+use crate::runtime::lock;
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+
+/// The request id of the invocation currently processed by the runtime.
+static REQUEST_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// Sets the request id added to log records, called by the runtime when an invocation begins and ends.
+pub(crate) fn set_request_id(request_id: Option<&str>) {
+    *lock(&REQUEST_ID) = request_id.map(|id| id.to_string());
+}
+
+/// A [`log`] backend writing records to stdout in the format configured by the
+/// [advanced logging controls](https://docs.aws.amazon.com/lambda/latest/dg/monitoring-cloudwatchlogs-advanced.html)
+/// of the function.
+///
+/// In [`LogFormat::Json`] each record is a JSON object with the `timestamp`, `level`, `message`, `logger` and `requestId` fields.
+/// In [`LogFormat::Text`] each record is a tab separated line, and newlines in the message are replaced by carriage returns
+/// so that a multi-line message is kept in a single CloudWatch log event.
+///
+/// Records below the configured level are discarded, `FATAL` is treated as [`log::Level::Error`] and the default level is [`log::Level::Info`].
+#[derive(Debug, Clone, Copy)]
+pub struct Logger {
+    format: LogFormat,
+    level: LevelFilter,
+}
+
+impl Logger {
+    pub fn new(format: LogFormat, level: Option<LogLevel>) -> Self {
+        let level = match level {
+            Some(LogLevel::Trace) => LevelFilter::Trace,
+            Some(LogLevel::Debug) => LevelFilter::Debug,
+            Some(LogLevel::Info) | None => LevelFilter::Info,
+            Some(LogLevel::Warn) => LevelFilter::Warn,
+            Some(LogLevel::Error) | Some(LogLevel::Fatal) => LevelFilter::Error,
+        };
+        Self { format, level }
+    }
+
+    /// Creates a logger configured by the `AWS_LAMBDA_LOG_FORMAT` and `AWS_LAMBDA_LOG_LEVEL` env-vars.
+    pub fn from_env_vars<E: RuntimeEnvVars>(env_vars: &E) -> Self {
+        Self::new(env_vars.get_log_format(), env_vars.get_log_level())
+    }
+
+    #[inline(always)]
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    #[inline(always)]
+    pub fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Installs the logger as the global [`log`] backend. Fails if a backend was already installed.
+    pub fn init(self) -> Result<(), Error> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))
+            .map_err(|err| Error::new(format!("Failed installing logger. {}", err)))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    /// Formats a record as a single line, without the line terminator.
+    fn format_record(&self, record: &Record) -> String {
+        let timestamp = format_timestamp(SystemTime::now());
+        let request_id = lock(&REQUEST_ID).clone();
+        let message = record.args().to_string();
+        match self.format {
+            LogFormat::Json => {
+                let mut line = json!({
+                    "timestamp": timestamp,
+                    "level": record.level().as_str(),
+                    "message": message,
+                    "logger": record.target(),
+                });
+                if let Some(request_id) = request_id {
+                    line["requestId"] = json!(request_id);
+                }
+                line.to_string()
+            }
+            LogFormat::Text => format!(
+                "{}\t{}\t{}\t{}",
+                timestamp,
+                request_id.as_deref().unwrap_or("undefined"),
+                record.level().as_str(),
+                message.replace("\r\n", "\r").replace('\n', "\r")
+            ),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format_record(record);
+        let _ = writeln!(std::io::stdout().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().lock().flush();
+    }
+}
+
+/// Installs a [`Logger`] configured by the `AWS_LAMBDA_LOG_FORMAT` and `AWS_LAMBDA_LOG_LEVEL` env-vars as the global [`log`] backend.
+pub fn init() -> Result<(), Error> {
+    Logger::from_env_vars(&LambdaRuntimeEnv::from_env()).init()
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp with millisecond precision, e.g. `2022-05-22T10:01:02.345Z`.
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use std::time::Duration;
+
+    fn format(logger: &Logger, level: Level, message: &str) -> String {
+        logger.format_record(
+            &Record::builder()
+                .level(level)
+                .target("orders")
+                .args(format_args!("{}", message))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn formats_json_and_text_records() {
+        let json = Logger::new(LogFormat::Json, None);
+        let text = Logger::new(LogFormat::Text, None);
+
+        set_request_id(Some("8476a536"));
+        let line: serde_json::Value =
+            serde_json::from_str(&format(&json, Level::Warn, "first\nsecond")).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "first\nsecond");
+        assert_eq!(line["logger"], "orders");
+        assert_eq!(line["requestId"], "8476a536");
+        assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+
+        let line = format(&text, Level::Info, "first\nsecond\r\nthird");
+        let fields: Vec<&str> = line.split('\t').collect();
+        assert_eq!(fields[1..], ["8476a536", "INFO", "first\rsecond\rthird"]);
+        assert!(!line.contains('\n'));
+
+        set_request_id(None);
+        let line: serde_json::Value =
+            serde_json::from_str(&format(&json, Level::Info, "outside")).unwrap();
+        assert!(line.get("requestId").is_none());
+        let line = format(&text, Level::Info, "outside");
+        assert_eq!(line.split('\t').nth(1), Some("undefined"));
+    }
+
+    #[test]
+    fn filters_records_by_the_configured_level() {
+        let logger = |level: &str| {
+            Logger::from_env_vars(
+                &LambdaRuntimeEnv::builder()
+                    .with_var("AWS_LAMBDA_LOG_FORMAT", "JSON")
+                    .with_var("AWS_LAMBDA_LOG_LEVEL", level)
+                    .build(),
+            )
+        };
+        let enabled = |logger: &Logger, level: Level| {
+            logger.enabled(&Metadata::builder().level(level).build())
+        };
+
+        let warn = logger("WARN");
+        assert_eq!(warn.format(), LogFormat::Json);
+        assert_eq!(warn.level(), LevelFilter::Warn);
+        assert!(enabled(&warn, Level::Error));
+        assert!(enabled(&warn, Level::Warn));
+        assert!(!enabled(&warn, Level::Info));
+
+        assert_eq!(logger("FATAL").level(), LevelFilter::Error);
+        assert_eq!(logger("TRACE").level(), LevelFilter::Trace);
+        let default = Logger::from_env_vars(&LambdaRuntimeEnv::builder().build());
+        assert_eq!(default.format(), LogFormat::Text);
+        assert_eq!(default.level(), LevelFilter::Info);
+        assert!(!enabled(&default, Level::Debug));
+    }
+
+    #[test]
+    fn formats_rfc_3339_timestamps() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_653_213_662_345);
+        assert_eq!(format_timestamp(time), "2022-05-22T10:01:02.345Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+}
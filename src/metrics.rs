@@ -0,0 +1,633 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::error::Error;
+use crate::runtime::lock;
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+/// The maximum number of metrics in a single EMF document.
+pub const MAX_METRICS_PER_DOCUMENT: usize = 100;
+/// The maximum number of values of a single metric in a single EMF document.
+pub const MAX_VALUES_PER_METRIC: usize = 100;
+/// The maximum number of dimensions in a dimension set.
+pub const MAX_DIMENSIONS: usize = 30;
+
+/// The key of the metadata object of EMF documents.
+const AWS_KEY: &str = "_aws";
+
+/// The unit of a metric, see [MetricDatum](https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_MetricDatum.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Seconds,
+    Microseconds,
+    Milliseconds,
+    Bytes,
+    Kilobytes,
+    Megabytes,
+    Gigabytes,
+    Terabytes,
+    Bits,
+    Kilobits,
+    Megabits,
+    Gigabits,
+    Terabits,
+    Percent,
+    Count,
+    BytesPerSecond,
+    KilobytesPerSecond,
+    MegabytesPerSecond,
+    GigabytesPerSecond,
+    TerabytesPerSecond,
+    BitsPerSecond,
+    KilobitsPerSecond,
+    MegabitsPerSecond,
+    GigabitsPerSecond,
+    TerabitsPerSecond,
+    CountPerSecond,
+    None,
+}
+
+impl Unit {
+    /// Returns the name of the unit as written in EMF documents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Seconds => "Seconds",
+            Self::Microseconds => "Microseconds",
+            Self::Milliseconds => "Milliseconds",
+            Self::Bytes => "Bytes",
+            Self::Kilobytes => "Kilobytes",
+            Self::Megabytes => "Megabytes",
+            Self::Gigabytes => "Gigabytes",
+            Self::Terabytes => "Terabytes",
+            Self::Bits => "Bits",
+            Self::Kilobits => "Kilobits",
+            Self::Megabits => "Megabits",
+            Self::Gigabits => "Gigabits",
+            Self::Terabits => "Terabits",
+            Self::Percent => "Percent",
+            Self::Count => "Count",
+            Self::BytesPerSecond => "Bytes/Second",
+            Self::KilobytesPerSecond => "Kilobytes/Second",
+            Self::MegabytesPerSecond => "Megabytes/Second",
+            Self::GigabytesPerSecond => "Gigabytes/Second",
+            Self::TerabytesPerSecond => "Terabytes/Second",
+            Self::BitsPerSecond => "Bits/Second",
+            Self::KilobitsPerSecond => "Kilobits/Second",
+            Self::MegabitsPerSecond => "Megabits/Second",
+            Self::GigabitsPerSecond => "Gigabits/Second",
+            Self::TerabitsPerSecond => "Terabits/Second",
+            Self::CountPerSecond => "Count/Second",
+            Self::None => "None",
+        }
+    }
+}
+
+/// The storage resolution of a metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// One minute resolution.
+    Standard,
+    /// One second resolution.
+    High,
+}
+
+impl Resolution {
+    /// Returns the resolution in seconds, the `StorageResolution` of EMF documents.
+    pub fn as_secs(&self) -> u64 {
+        match self {
+            Self::Standard => 60,
+            Self::High => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Metric {
+    name: String,
+    unit: Unit,
+    resolution: Resolution,
+    values: Vec<f64>,
+}
+
+/// Collects metrics and writes them to stdout in the CloudWatch
+/// [Embedded Metric Format](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html),
+/// from which CloudWatch extracts them asynchronously.
+///
+/// When passed to [`crate::runtime::DefaultRuntime::enable_metrics`], a copy of the logger is used as the current logger
+/// of each invocation - accessible to the handler using [`with_current`] and [`put_metric`] - and is flushed after the handler returns.
+///
+/// Values exceeding the EMF limits of [`MAX_METRICS_PER_DOCUMENT`] and [`MAX_VALUES_PER_METRIC`] are split across multiple documents.
+#[derive(Debug, Clone)]
+pub struct MetricsLogger {
+    namespace: String,
+    dimensions: Vec<Vec<(String, String)>>,
+    properties: Map<String, Value>,
+    metrics: Vec<Metric>,
+}
+
+impl MetricsLogger {
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            dimensions: Vec::new(),
+            properties: Map::new(),
+            metrics: Vec::new(),
+        }
+    }
+
+    /// Adds a dimension set to the logger, builder style. See [`MetricsLogger::put_dimensions`].
+    pub fn with_dimensions(mut self, dimensions: &[(&str, &str)]) -> Result<Self, Error> {
+        self.put_dimensions(dimensions)?;
+        Ok(self)
+    }
+
+    #[inline(always)]
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn set_namespace(&mut self, namespace: &str) {
+        self.namespace = namespace.to_string();
+    }
+
+    /// Adds a set of dimensions - name and value pairs - that all metrics are aggregated by.
+    ///
+    /// Fails if the set has more than [`MAX_DIMENSIONS`] dimensions, a dimension has an empty name or value,
+    /// or a dimension name is repeated or already used by a metric or a property.
+    /// Sets giving different values to the same dimension are written to separate documents.
+    pub fn put_dimensions(&mut self, dimensions: &[(&str, &str)]) -> Result<(), Error> {
+        if dimensions.len() > MAX_DIMENSIONS {
+            return Err(Error::new(format!(
+                "Dimension set has {} dimensions, the maximum is {}",
+                dimensions.len(),
+                MAX_DIMENSIONS
+            )));
+        }
+        if let Some((name, _)) = dimensions
+            .iter()
+            .find(|(name, value)| name.is_empty() || value.is_empty())
+        {
+            return Err(Error::new(format!(
+                "Invalid dimension `{}`. Dimension names and values must not be empty",
+                name
+            )));
+        }
+        for (i, (name, _)) in dimensions.iter().enumerate() {
+            if dimensions[..i].iter().any(|(other, _)| other == name) {
+                return Err(Error::new(format!(
+                    "Dimension `{}` is repeated in the dimension set",
+                    name
+                )));
+            }
+            if self.is_metric(name) || self.properties.contains_key(*name) || *name == AWS_KEY {
+                return Err(Error::new(format!(
+                    "Dimension `{}` has the name of a metric or a property",
+                    name
+                )));
+            }
+        }
+        self.dimensions.push(
+            dimensions
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    /// Removes all dimension sets, including the ones of the logger passed to the runtime.
+    pub fn clear_dimensions(&mut self) {
+        self.dimensions.clear();
+    }
+
+    /// Sets a property - a value written to the document that isn't a metric or a dimension, useful when querying the logs.
+    ///
+    /// Fails if the key is already used by a metric or a dimension.
+    pub fn set_property<V: Into<Value>>(&mut self, key: &str, value: V) -> Result<(), Error> {
+        if self.is_metric(key) || self.is_dimension(key) || key == AWS_KEY {
+            return Err(Error::new(format!(
+                "Property `{}` has the name of a metric or a dimension",
+                key
+            )));
+        }
+        self.properties.insert(key.to_string(), value.into());
+        Ok(())
+    }
+
+    /// Records a value of a metric with a standard resolution.
+    pub fn put_metric(&mut self, name: &str, value: f64, unit: Unit) -> Result<(), Error> {
+        self.put_metric_with_resolution(name, value, unit, Resolution::Standard)
+    }
+
+    /// Records a value of a metric.
+    ///
+    /// Fails if the value isn't finite, the name is already used by a dimension or a property,
+    /// or the metric was already recorded with a different unit or resolution.
+    pub fn put_metric_with_resolution(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: Unit,
+        resolution: Resolution,
+    ) -> Result<(), Error> {
+        if name.is_empty() {
+            return Err(Error::new("Metric names must not be empty".to_string()));
+        }
+        if !value.is_finite() {
+            return Err(Error::new(format!(
+                "Invalid value {} for metric `{}`",
+                value, name
+            )));
+        }
+        let collides =
+            self.is_dimension(name) || self.properties.contains_key(name) || name == AWS_KEY;
+        match self.metrics.iter_mut().find(|m| m.name == name) {
+            Some(metric) if metric.unit != unit || metric.resolution != resolution => {
+                Err(Error::new(format!(
+                    "Metric `{}` was already recorded with unit {} and {:?} resolution",
+                    name,
+                    metric.unit.as_str(),
+                    metric.resolution
+                )))
+            }
+            Some(metric) => {
+                metric.values.push(value);
+                Ok(())
+            }
+            None if collides => Err(Error::new(format!(
+                "Metric `{}` has the name of a dimension or a property",
+                name
+            ))),
+            None => {
+                self.metrics.push(Metric {
+                    name: name.to_string(),
+                    unit,
+                    resolution,
+                    values: vec![value],
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn is_metric(&self, name: &str) -> bool {
+        self.metrics.iter().any(|m| m.name == name)
+    }
+
+    fn is_dimension(&self, name: &str) -> bool {
+        self.dimensions.iter().flatten().any(|(n, _)| n == name)
+    }
+
+    /// Returns `true` if no metric values were recorded since the last flush.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+    }
+
+    /// Formats the recorded metrics as EMF documents, splitting them as needed to stay within the EMF limits.
+    ///
+    /// Dimension sets are written together unless they give different values to the same dimension,
+    /// in which case the metrics are written once for each group of compatible sets.
+    pub fn to_documents(&self, timestamp: SystemTime) -> Vec<Value> {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let mut groups: Vec<DimensionGroup> = Vec::new();
+        for set in &self.dimensions {
+            let index = match groups.iter().position(|group| group.accepts(set)) {
+                Some(index) => index,
+                None => {
+                    groups.push(DimensionGroup::default());
+                    groups.len() - 1
+                }
+            };
+            groups[index].push(set);
+        }
+        // Metrics without any dimension set are published using a single empty one.
+        if groups.is_empty() {
+            groups.push(DimensionGroup {
+                values: Map::new(),
+                sets: vec![Vec::new()],
+            });
+        }
+
+        let mut documents = Vec::new();
+        for group in &groups {
+            self.write_documents(timestamp, &group.values, &group.sets, &mut documents);
+        }
+        documents
+    }
+
+    /// Appends the documents of a group of dimension sets, each holding the next chunk of values
+    /// of up to `MAX_METRICS_PER_DOCUMENT` metrics that have values left.
+    fn write_documents(
+        &self,
+        timestamp: u64,
+        dimension_values: &Map<String, Value>,
+        dimension_sets: &[Vec<&str>],
+        documents: &mut Vec<Value>,
+    ) {
+        let mut offsets = vec![0; self.metrics.len()];
+        loop {
+            let mut document = self.properties.clone();
+            document.extend(dimension_values.clone());
+            let mut definitions = Vec::new();
+            for (metric, offset) in self.metrics.iter().zip(offsets.iter_mut()) {
+                if definitions.len() == MAX_METRICS_PER_DOCUMENT {
+                    break;
+                }
+                if *offset == metric.values.len() {
+                    continue;
+                }
+                let end = metric.values.len().min(*offset + MAX_VALUES_PER_METRIC);
+                let values = &metric.values[*offset..end];
+                *offset = end;
+                document.insert(
+                    metric.name.clone(),
+                    match values {
+                        [value] => json!(value),
+                        values => json!(values),
+                    },
+                );
+                let mut definition = json!({ "Name": metric.name, "Unit": metric.unit.as_str() });
+                if metric.resolution == Resolution::High {
+                    definition["StorageResolution"] = json!(metric.resolution.as_secs());
+                }
+                definitions.push(definition);
+            }
+            if definitions.is_empty() {
+                break;
+            }
+            document.insert(
+                AWS_KEY.to_string(),
+                json!({
+                    "Timestamp": timestamp,
+                    "CloudWatchMetrics": [{
+                        "Namespace": self.namespace,
+                        "Dimensions": dimension_sets,
+                        "Metrics": definitions,
+                    }],
+                }),
+            );
+            documents.push(Value::Object(document));
+        }
+    }
+
+    /// Writes the recorded metrics to stdout and clears them, keeping the namespace, dimensions and properties.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let documents = self.to_documents(SystemTime::now());
+        self.metrics.clear();
+        let mut stdout = std::io::stdout().lock();
+        for document in documents {
+            writeln!(stdout, "{}", document)
+                .map_err(|err| Error::new(format!("Failed writing metrics. {}", err)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Dimension sets written to the same documents, which don't give different values to the same dimension.
+#[derive(Default)]
+struct DimensionGroup<'a> {
+    values: Map<String, Value>,
+    sets: Vec<Vec<&'a str>>,
+}
+
+impl<'a> DimensionGroup<'a> {
+    fn accepts(&self, set: &[(String, String)]) -> bool {
+        set.iter().all(|(name, value)| {
+            self.values
+                .get(name)
+                .is_none_or(|existing| existing.as_str() == Some(value))
+        })
+    }
+
+    fn push(&mut self, set: &'a [(String, String)]) {
+        for (name, value) in set {
+            self.values.insert(name.clone(), json!(value));
+        }
+        self.sets
+            .push(set.iter().map(|(name, _)| name.as_str()).collect());
+    }
+}
+
+/// The metrics logger of the invocation currently processed by the runtime.
+static CURRENT: Mutex<Option<MetricsLogger>> = Mutex::new(None);
+
+/// Calls `f` with the metrics logger of the current invocation.
+///
+/// Returns `None` when called outside of an invocation, or if metrics weren't enabled on the runtime.
+/// The logger is taken out of the invocation while `f` runs, so nested calls made by `f` return `None`.
+pub fn with_current<F, T>(f: F) -> Option<T>
+where
+    F: FnOnce(&mut MetricsLogger) -> T,
+{
+    // The lock isn't held while calling `f`, which may call back into this module.
+    let mut metrics = lock(&CURRENT).take()?;
+    let result = f(&mut metrics);
+    *lock(&CURRENT) = Some(metrics);
+    Some(result)
+}
+
+/// Records a value of a metric with a standard resolution using the metrics logger of the current invocation.
+///
+/// Fails when called outside of an invocation, or if metrics weren't enabled on the runtime.
+pub fn put_metric(name: &str, value: f64, unit: Unit) -> Result<(), Error> {
+    with_current(|metrics| metrics.put_metric(name, value, unit))
+        .unwrap_or_else(|| Err(Error::new("Metrics are not enabled".to_string())))
+}
+
+/// Sets a copy of `template` as the metrics logger of a new invocation.
+pub(crate) fn begin_invocation(template: &MetricsLogger, request_id: &str) {
+    let mut metrics = template.clone();
+    metrics
+        .properties
+        .insert("requestId".to_string(), json!(request_id));
+    *lock(&CURRENT) = Some(metrics);
+}
+
+/// Flushes the metrics logger of the current invocation.
+pub(crate) fn end_invocation() {
+    if let Some(mut metrics) = lock(&CURRENT).take() {
+        if let Err(err) = metrics.flush() {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics_of(document: &Value) -> &Vec<Value> {
+        document["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+            .as_array()
+            .unwrap()
+    }
+
+    #[test]
+    fn splits_metrics_across_documents() {
+        let mut logger = MetricsLogger::new("test");
+        for i in 0..MAX_METRICS_PER_DOCUMENT + 50 {
+            logger
+                .put_metric(&format!("metric{}", i), i as f64, Unit::Count)
+                .unwrap();
+        }
+        let documents = logger.to_documents(UNIX_EPOCH);
+        assert_eq!(documents.len(), 2);
+        assert_eq!(metrics_of(&documents[0]).len(), MAX_METRICS_PER_DOCUMENT);
+        assert_eq!(metrics_of(&documents[1]).len(), 50);
+        assert_eq!(documents[0]["metric0"], json!(0.0));
+        assert_eq!(documents[1]["metric149"], json!(149.0));
+        assert!(documents[1].get("metric0").is_none());
+    }
+
+    #[test]
+    fn splits_values_across_documents() {
+        let mut logger = MetricsLogger::new("test");
+        for i in 0..MAX_VALUES_PER_METRIC * 2 + 1 {
+            logger
+                .put_metric_with_resolution(
+                    "latency",
+                    i as f64,
+                    Unit::Milliseconds,
+                    Resolution::High,
+                )
+                .unwrap();
+        }
+        let documents = logger.to_documents(UNIX_EPOCH);
+        assert_eq!(documents.len(), 3);
+        assert_eq!(
+            documents[0]["latency"].as_array().unwrap().len(),
+            MAX_VALUES_PER_METRIC
+        );
+        assert_eq!(
+            documents[1]["latency"].as_array().unwrap().len(),
+            MAX_VALUES_PER_METRIC
+        );
+        // A single value is written as a number rather than an array.
+        assert_eq!(documents[2]["latency"], json!(200.0));
+        assert_eq!(
+            metrics_of(&documents[2])[0],
+            json!({ "Name": "latency", "Unit": "Milliseconds", "StorageResolution": 1 })
+        );
+    }
+
+    #[test]
+    fn writes_the_namespace_dimensions_and_properties() {
+        let mut logger = MetricsLogger::new("test")
+            .with_dimensions(&[("service", "orders")])
+            .unwrap()
+            .with_dimensions(&[("service", "orders"), ("stage", "prod")])
+            .unwrap();
+        logger.set_property("version", 3).unwrap();
+        logger.put_metric("count", 1.0, Unit::Count).unwrap();
+        let documents = logger.to_documents(UNIX_EPOCH + std::time::Duration::from_millis(42));
+        assert_eq!(
+            documents,
+            vec![json!({
+                "service": "orders",
+                "stage": "prod",
+                "version": 3,
+                "count": 1.0,
+                "_aws": {
+                    "Timestamp": 42,
+                    "CloudWatchMetrics": [{
+                        "Namespace": "test",
+                        "Dimensions": [["service"], ["service", "stage"]],
+                        "Metrics": [{ "Name": "count", "Unit": "Count" }],
+                    }],
+                },
+            })]
+        );
+    }
+
+    #[test]
+    fn writes_conflicting_dimension_sets_to_separate_documents() {
+        let mut logger = MetricsLogger::new("test")
+            .with_dimensions(&[("service", "orders")])
+            .unwrap()
+            .with_dimensions(&[("service", "payments")])
+            .unwrap()
+            .with_dimensions(&[("stage", "prod")])
+            .unwrap();
+        logger.put_metric("count", 1.0, Unit::Count).unwrap();
+        let documents = logger.to_documents(UNIX_EPOCH);
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0]["service"], "orders");
+        assert_eq!(documents[0]["stage"], "prod");
+        assert_eq!(
+            documents[0]["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            json!([["service"], ["stage"]])
+        );
+        assert_eq!(documents[1]["service"], "payments");
+        assert!(documents[1].get("stage").is_none());
+        assert_eq!(documents[1]["count"], json!(1.0));
+    }
+
+    #[test]
+    fn enforces_the_limits() {
+        let names: Vec<String> = (0..=MAX_DIMENSIONS).map(|i| format!("d{}", i)).collect();
+        let dimensions: Vec<(&str, &str)> = names.iter().map(|n| (n.as_str(), "value")).collect();
+        let mut logger = MetricsLogger::new("test");
+        assert!(logger.put_dimensions(&dimensions).is_err());
+        assert!(logger.put_dimensions(&dimensions[..MAX_DIMENSIONS]).is_ok());
+        assert!(logger.put_dimensions(&[("", "value")]).is_err());
+        assert!(logger.put_dimensions(&[("name", "")]).is_err());
+        assert!(logger.put_dimensions(&[("a", "1"), ("a", "2")]).is_err());
+
+        assert!(logger.put_metric("", 1.0, Unit::Count).is_err());
+        assert!(logger.put_metric("nan", f64::NAN, Unit::Count).is_err());
+        logger.put_metric("size", 1.0, Unit::Bytes).unwrap();
+        assert!(logger.put_metric("size", 1.0, Unit::Kilobytes).is_err());
+        assert!(logger
+            .put_metric_with_resolution("size", 1.0, Unit::Bytes, Resolution::High)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_colliding_keys() {
+        let mut logger = MetricsLogger::new("test")
+            .with_dimensions(&[("service", "orders")])
+            .unwrap();
+        logger.set_property("version", 3).unwrap();
+        logger.put_metric("count", 1.0, Unit::Count).unwrap();
+
+        assert!(logger.put_metric("service", 1.0, Unit::Count).is_err());
+        assert!(logger.put_metric("version", 1.0, Unit::Count).is_err());
+        assert!(logger.put_metric("_aws", 1.0, Unit::Count).is_err());
+        assert!(logger.put_dimensions(&[("count", "1")]).is_err());
+        assert!(logger.put_dimensions(&[("version", "1")]).is_err());
+        assert!(logger.set_property("count", 1).is_err());
+        assert!(logger.set_property("service", "payments").is_err());
+        // Existing properties may be overwritten.
+        assert!(logger.set_property("version", 4).is_ok());
+    }
+
+    #[test]
+    fn nested_calls_to_with_current_do_not_deadlock() {
+        begin_invocation(&MetricsLogger::new("test"), "request");
+        let nested = with_current(|metrics| {
+            metrics.put_metric("count", 1.0, Unit::Count).unwrap();
+            with_current(|_| ())
+        });
+        assert_eq!(nested, Some(None));
+        assert!(put_metric("count", 2.0, Unit::Count).is_ok());
+        let documents = with_current(|metrics| metrics.to_documents(UNIX_EPOCH)).unwrap();
+        assert_eq!(documents[0]["count"], json!([1.0, 2.0]));
+        assert_eq!(documents[0]["requestId"], "request");
+        *lock(&CURRENT) = None;
+        assert!(put_metric("count", 3.0, Unit::Count).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::data::context::LambdaContext;
+use crate::data::trace::TraceHeader;
+
+use opentelemetry::trace::{
+    SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId, TraceState,
+    Tracer, TracerProvider,
+};
+use opentelemetry::{Context, ContextGuard, KeyValue};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+
+/// The name of the tracer used for the invocation spans.
+pub static TRACER_NAME: &str = "rtlambda";
+
+/// Records an OpenTelemetry server span for each invocation processed by [`crate::runtime::DefaultRuntime`].
+///
+/// The span carries the FaaS semantic-convention attributes and is parented by the span referenced in the
+/// `Lambda-Runtime-Trace-Id` header. While the handler runs, the span's context is the current context,
+/// so spans started by the handler using [`Context::current`] are nested in it.
+///
+/// Spans are exported by the span processors of the tracer provider, which is flushed before the runtime
+/// polls for the next event since the execution environment may be frozen until then.
+/// Created by [`crate::runtime::DefaultRuntime::enable_opentelemetry`].
+pub struct Instrumentation {
+    provider: SdkTracerProvider,
+    tracer: SdkTracer,
+    invocation: Option<Context>,
+}
+
+impl Instrumentation {
+    pub fn new(provider: SdkTracerProvider) -> Self {
+        Self {
+            tracer: provider.tracer(TRACER_NAME),
+            provider,
+            invocation: None,
+        }
+    }
+
+    /// Starts the span of a new invocation and sets it as the current context until the returned guard is dropped.
+    pub(crate) fn begin_invocation<C: LambdaContext>(&mut self, context: &C) -> ContextGuard {
+        let name = context.function_name().unwrap_or("invocation").to_string();
+        let mut attributes = vec![KeyValue::new("faas.coldstart", context.is_cold_start())];
+        if let Some(request_id) = context.aws_request_id() {
+            attributes.push(KeyValue::new("faas.invocation_id", request_id.to_string()));
+        }
+        if let Some(function_name) = context.function_name() {
+            attributes.push(KeyValue::new("faas.name", function_name.to_string()));
+        }
+        if let Some(function_version) = context.function_version() {
+            attributes.push(KeyValue::new("faas.version", function_version.to_string()));
+        }
+        if let Some(arn) = context.invoked_function_arn() {
+            attributes.push(KeyValue::new("cloud.resource_id", arn.to_string()));
+        }
+
+        let parent = match context.trace_header() {
+            Ok(Some(header)) => parent_context(&header),
+            _ => Context::new(),
+        };
+        let span = self
+            .tracer
+            .span_builder(name)
+            .with_kind(SpanKind::Server)
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &parent);
+        let invocation = parent.with_span(span);
+        self.invocation = Some(invocation.clone());
+        invocation.attach()
+    }
+
+    /// Ends the span of the current invocation, setting an error status with `error` as the description if the handler failed.
+    pub(crate) fn end_invocation(&mut self, error: Option<String>) {
+        if let Some(invocation) = self.invocation.take() {
+            let span = invocation.span();
+            if let Some(error) = error {
+                span.set_status(Status::error(error));
+            }
+            span.end();
+        }
+    }
+
+    /// Forces the tracer provider to export all ended spans.
+    pub(crate) fn flush(&self) {
+        if let Err(err) = self.provider.force_flush() {
+            eprintln!("Failed flushing OpenTelemetry spans. {}", err);
+        }
+    }
+}
+
+/// Creates a context whose remote parent span is the one referenced by an X-Ray trace header.
+/// Returns an empty context if the header has no valid parent.
+fn parent_context(header: &TraceHeader) -> Context {
+    let traceparent = match header.to_traceparent() {
+        Ok(traceparent) => traceparent,
+        Err(_) => return Context::new(),
+    };
+    // The traceparent is validated: `00-{trace id}-{parent id}-{flags}`.
+    let fields: Vec<&str> = traceparent.split('-').collect();
+    let (trace_id, span_id) = match (TraceId::from_hex(fields[1]), SpanId::from_hex(fields[2])) {
+        (Ok(trace_id), Ok(span_id)) => (trace_id, span_id),
+        _ => return Context::new(),
+    };
+    let flags = if header.is_sampled() {
+        TraceFlags::SAMPLED
+    } else {
+        TraceFlags::NOT_SAMPLED
+    };
+    Context::new().with_remote_span_context(SpanContext::new(
+        trace_id,
+        span_id,
+        flags,
+        true,
+        TraceState::NONE,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::context::RefLambdaContext;
+    use crate::data::env::LambdaRuntimeEnv;
+    use crate::data::response::TestResponse;
+
+    use opentelemetry::Value;
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Collects the ended spans.
+    #[derive(Debug, Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanProcessor for Collector {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_a_server_span_with_the_faas_attributes() {
+        let collector = Collector::default();
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(collector.clone())
+            .build();
+        let mut instrumentation = Instrumentation::new(provider);
+
+        let env_vars = LambdaRuntimeEnv::builder()
+            .with_function_name("orders")
+            .build();
+        let response = TestResponse {
+            aws_request_id: Some("8476a536".to_string()),
+            invoked_function_arn: Some(
+                "arn:aws:lambda:us-east-1:123456789012:function:orders".to_string(),
+            ),
+            trace_id: Some(
+                "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1"
+                    .to_string(),
+            ),
+            ..Default::default()
+        };
+        let context = RefLambdaContext::new(&env_vars, &response).with_invocation_index(1);
+        drop(instrumentation.begin_invocation(&context));
+        instrumentation.end_invocation(Some("failed".to_string()));
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "orders");
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(span.status, Status::error("failed"));
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from_hex("5759e988bd862e3fe1be46a994272793").unwrap()
+        );
+        assert_eq!(
+            span.parent_span_id,
+            SpanId::from_hex("53995c3f42cd8ad8").unwrap()
+        );
+        assert!(span.parent_span_is_remote);
+
+        let attribute = |key: &str| {
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.clone())
+        };
+        assert_eq!(attribute("faas.coldstart"), Some(Value::Bool(false)));
+        assert_eq!(attribute("faas.invocation_id"), Some("8476a536".into()));
+        assert_eq!(attribute("faas.name"), Some("orders".into()));
+        assert_eq!(attribute("faas.version"), Some("$LATEST".into()));
+        assert_eq!(
+            attribute("cloud.resource_id"),
+            Some("arn:aws:lambda:us-east-1:123456789012:function:orders".into())
+        );
+    }
+
+    #[test]
+    fn starts_a_new_trace_without_a_valid_parent() {
+        let collector = Collector::default();
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(collector.clone())
+            .build();
+        let mut instrumentation = Instrumentation::new(provider);
+
+        let env_vars = LambdaRuntimeEnv::builder().build();
+        let response = TestResponse {
+            trace_id: Some("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1".to_string()),
+            ..Default::default()
+        };
+        let context = RefLambdaContext::new(&env_vars, &response);
+        drop(instrumentation.begin_invocation(&context));
+        instrumentation.end_invocation(None);
+
+        let spans = collector.0.lock().unwrap();
+        let span = &spans[0];
+        assert_eq!(span.name, "local-function");
+        assert_eq!(span.parent_span_id, SpanId::INVALID);
+        assert_eq!(span.status, Status::Unset);
+        assert!(span
+            .attributes
+            .contains(&KeyValue::new("faas.coldstart", true)));
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::data::context::{ContextKind, RefContextKind};
+use crate::data::env::RuntimeEnvVars;
+use crate::data::response::LambdaAPIResponse;
+use crate::error::Error;
+use crate::runtime::stats::time_deserialize;
+use crate::runtime::EventHandler;
+
+use std::fmt::{Display, Formatter};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// The source of an event, detected from the shape of its JSON by the rules of an [`EventRouter`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EventSource {
+    /// Records with an `eventSource` of `aws:sqs`.
+    Sqs,
+    /// Records with an `EventSource` of `aws:sns`.
+    Sns,
+    /// Records with an `eventSource` of `aws:s3`.
+    S3,
+    /// Records with an `eventSource` of `aws:dynamodb`.
+    DynamoDb,
+    /// Records with an `eventSource` of `aws:kinesis`.
+    Kinesis,
+    /// An EventBridge event with the given `detail-type`, e.g. `Scheduled Event` for scheduled rules.
+    EventBridge(String),
+    /// A CloudWatch Logs subscription event, which has an `awslogs` object.
+    CloudWatchLogs,
+    /// An HTTP event - from API Gateway, an ALB or a function URL - which has a `requestContext` object.
+    Http,
+    /// Records with any other event source, or a source detected by a user-defined rule.
+    Custom(String),
+}
+
+impl EventSource {
+    /// The source of events sent by EventBridge scheduled rules.
+    pub fn scheduled() -> Self {
+        Self::EventBridge("Scheduled Event".to_string())
+    }
+}
+
+impl Display for EventSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sqs => write!(f, "aws:sqs"),
+            Self::Sns => write!(f, "aws:sns"),
+            Self::S3 => write!(f, "aws:s3"),
+            Self::DynamoDb => write!(f, "aws:dynamodb"),
+            Self::Kinesis => write!(f, "aws:kinesis"),
+            Self::EventBridge(detail_type) => write!(f, "EventBridge ({})", detail_type),
+            Self::CloudWatchLogs => write!(f, "CloudWatch Logs"),
+            Self::Http => write!(f, "HTTP"),
+            Self::Custom(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+/// A rule that detects the source of an event from its JSON, or returns `None` if it doesn't recognize it.
+pub type DetectionRule = Box<dyn Fn(&Value) -> Option<EventSource>>;
+
+/// A handler of a route, accepting the parsed event.
+type RouteHandler<ENV, R, OUT, ERR, CTX> =
+    Box<dyn for<'a> Fn(Value, <CTX as ContextKind<ENV, R>>::Context<'a>) -> Result<OUT, ERR>>;
+
+/// An event source and the handler its events are routed to.
+type Route<ENV, R, OUT, ERR, CTX> = (EventSource, RouteHandler<ENV, R, OUT, ERR, CTX>);
+
+/// Dispatches each event to the handler of the [`EventSource`] detected from its shape, for functions triggered by several sources.
+///
+/// The source is detected by the rules added using [`EventRouter::with_rule`], in the order they were added,
+/// and then by the built-in rules - see [`detect_source`].
+/// Events whose source is not detected or has no route, as well as empty or non-JSON events, are passed to the fallback handler.
+///
+/// The router is converted into an [`EventHandler`] - to be returned from the initializer - using [`EventRouter::into_handler`].
+pub struct EventRouter<ENV, R, OUT, ERR, CTX = RefContextKind>
+where
+    ENV: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+    CTX: ContextKind<ENV, R>,
+{
+    rules: Vec<DetectionRule>,
+    routes: Vec<Route<ENV, R, OUT, ERR, CTX>>,
+    fallback: Option<EventHandler<ENV, R, OUT, ERR, CTX>>,
+}
+
+impl<ENV, R, OUT, ERR, CTX> EventRouter<ENV, R, OUT, ERR, CTX>
+where
+    ENV: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+    CTX: ContextKind<ENV, R>,
+{
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Adds a rule for detecting the source of an event, evaluated before the built-in rules.
+    pub fn with_rule<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(&Value) -> Option<EventSource> + 'static,
+    {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Sets the handler of events that are not routed to any other handler.
+    ///
+    /// Without a fallback handler, such events fail with an error.
+    pub fn with_fallback(mut self, handler: EventHandler<ENV, R, OUT, ERR, CTX>) -> Self {
+        self.fallback = Some(handler);
+        self
+    }
+
+    /// Returns the source of `event`, detected by the rules of the router.
+    pub fn detect(&self, event: &Value) -> Option<EventSource> {
+        self.rules
+            .iter()
+            .find_map(|rule| rule(event))
+            .or_else(|| detect_source(event))
+    }
+}
+
+impl<ENV, R, OUT, ERR, CTX> EventRouter<ENV, R, OUT, ERR, CTX>
+where
+    ENV: RuntimeEnvVars + 'static,
+    R: LambdaAPIResponse + 'static,
+    OUT: 'static,
+    ERR: From<Error> + 'static,
+    CTX: ContextKind<ENV, R> + 'static,
+{
+    /// Routes events from `source` to `handler`, which accepts the event deserialized from JSON into `IN`.
+    /// Replaces any handler previously routed from `source`.
+    ///
+    /// Errors during deserialization are converted into the handler's error type.
+    pub fn with_route<IN, F>(mut self, source: EventSource, handler: F) -> Self
+    where
+        IN: DeserializeOwned,
+        F: for<'a> Fn(IN, <CTX as ContextKind<ENV, R>>::Context<'a>) -> Result<OUT, ERR> + 'static,
+    {
+        let route: RouteHandler<ENV, R, OUT, ERR, CTX> = Box::new(move |event, context| {
+            let payload =
+                time_deserialize(|| serde_json::from_value::<IN>(event)).map_err(|err| {
+                    Error::new(format!("Failed deserializing event from JSON. {}", err))
+                })?;
+            handler(payload, context)
+        });
+        self.routes.retain(|(routed, _)| *routed != source);
+        self.routes.push((source, route));
+        self
+    }
+
+    /// Converts the router into an [`EventHandler`] that can be returned from the initializer of a [`crate::runtime::DefaultRuntime`].
+    pub fn into_handler(self) -> EventHandler<ENV, R, OUT, ERR, CTX> {
+        Box::new(move |event, context| {
+            let parsed = event
+                .and_then(|event| time_deserialize(|| serde_json::from_str::<Value>(event)).ok());
+            if let Some(parsed) = parsed {
+                let route = self.detect(&parsed).and_then(|source| {
+                    self.routes
+                        .iter()
+                        .find(|(routed, _)| *routed == source)
+                        .map(|(_, route)| route)
+                });
+                if let Some(route) = route {
+                    return route(parsed, context);
+                }
+            }
+            match &self.fallback {
+                Some(fallback) => fallback(event, context),
+                None => Err(Error::new("No handler is routed for the event".to_string()).into()),
+            }
+        })
+    }
+}
+
+impl<ENV, R, OUT, ERR, CTX> Default for EventRouter<ENV, R, OUT, ERR, CTX>
+where
+    ENV: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+    CTX: ContextKind<ENV, R>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detects the source of an event using the built-in rules, in order:
+/// - `Records[0].eventSource` (or `EventSource` for SNS) for SQS, SNS, S3, DynamoDB, Kinesis and other record based events.
+/// - `detail-type` and `source` for EventBridge events.
+/// - `awslogs` for CloudWatch Logs subscriptions.
+/// - `requestContext` for HTTP events.
+pub fn detect_source(event: &Value) -> Option<EventSource> {
+    if let Some(record) = event.get("Records").and_then(|records| records.get(0)) {
+        let source = record
+            .get("eventSource")
+            .or_else(|| record.get("EventSource"))
+            .and_then(Value::as_str)?;
+        return Some(match source {
+            "aws:sqs" => EventSource::Sqs,
+            "aws:sns" => EventSource::Sns,
+            "aws:s3" => EventSource::S3,
+            "aws:dynamodb" => EventSource::DynamoDb,
+            "aws:kinesis" => EventSource::Kinesis,
+            other => EventSource::Custom(other.to_string()),
+        });
+    }
+    if let (Some(detail_type), Some(_)) = (
+        event.get("detail-type").and_then(Value::as_str),
+        event.get("source"),
+    ) {
+        return Some(EventSource::EventBridge(detail_type.to_string()));
+    }
+    if event.get("awslogs").is_some() {
+        return Some(EventSource::CloudWatchLogs);
+    }
+    if event.get("requestContext").is_some() {
+        return Some(EventSource::Http);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::context::RefLambdaContext;
+    use crate::data::env::LambdaRuntimeEnv;
+    use crate::data::response::TestResponse;
+
+    use serde::Deserialize;
+    use serde_json::json;
+
+    type Router = EventRouter<LambdaRuntimeEnv, TestResponse, String, Error>;
+
+    const SQS: &str = r#"{"Records": [{"messageId": "059f3694", "body": "Test message.", "eventSource": "aws:sqs", "awsRegion": "us-east-1"}]}"#;
+    const SNS: &str = r#"{"Records": [{"EventSource": "aws:sns", "Sns": {"Subject": "TestInvoke", "Message": "Hello from SNS!"}}]}"#;
+    const S3: &str = r#"{"Records": [{"eventSource": "aws:s3", "eventName": "ObjectCreated:Put", "s3": {"bucket": {"name": "example-bucket"}, "object": {"key": "test/key"}}}]}"#;
+    const SCHEDULED: &str = r#"{"version": "0", "id": "53dc4d37", "detail-type": "Scheduled Event", "source": "aws.events", "detail": {}}"#;
+    const HTTP: &str = r#"{"version": "2.0", "rawPath": "/orders", "requestContext": {"http": {"method": "GET", "path": "/orders"}}}"#;
+
+    #[derive(Deserialize)]
+    struct Records<T> {
+        #[serde(rename = "Records")]
+        records: Vec<T>,
+    }
+
+    #[derive(Deserialize)]
+    struct SqsMessage {
+        body: String,
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .with_route(EventSource::Sqs, |event: Records<SqsMessage>, _| {
+                Ok(format!("sqs {}", event.records[0].body))
+            })
+            .with_route(EventSource::Sns, |event: Records<Value>, _| {
+                Ok(format!("sns {}", event.records[0]["Sns"]["Message"]))
+            })
+            .with_route(EventSource::S3, |event: Records<Value>, _| {
+                Ok(format!("s3 {}", event.records[0]["s3"]["object"]["key"]))
+            })
+            .with_route(EventSource::scheduled(), |_: Value, _| {
+                Ok("scheduled".to_string())
+            })
+            .with_route(EventSource::Http, |event: Value, _| {
+                Ok(format!("http {}", event["rawPath"]))
+            })
+    }
+
+    fn handle(router: Router, event: Option<&str>) -> Result<String, Error> {
+        let env_vars = LambdaRuntimeEnv::builder().build();
+        let response = TestResponse::default();
+        router.into_handler()(event, RefLambdaContext::new(&env_vars, &response))
+    }
+
+    #[test]
+    fn detects_the_built_in_sources() {
+        let detect = |event: &str| detect_source(&serde_json::from_str(event).unwrap());
+        assert_eq!(detect(SQS), Some(EventSource::Sqs));
+        assert_eq!(detect(SNS), Some(EventSource::Sns));
+        assert_eq!(detect(S3), Some(EventSource::S3));
+        assert_eq!(detect(SCHEDULED), Some(EventSource::scheduled()));
+        assert_eq!(detect(HTTP), Some(EventSource::Http));
+        assert_eq!(
+            detect(r#"{"Records": [{"eventSource": "aws:kinesis"}]}"#),
+            Some(EventSource::Kinesis)
+        );
+        assert_eq!(
+            detect(r#"{"Records": [{"eventSource": "aws:ses"}]}"#),
+            Some(EventSource::Custom("aws:ses".to_string()))
+        );
+        assert_eq!(
+            detect(r#"{"awslogs": {"data": "H4sI"}}"#),
+            Some(EventSource::CloudWatchLogs)
+        );
+        assert_eq!(detect(r#"{"Records": [{}]}"#), None);
+        assert_eq!(detect(r#"{"orderId": 42}"#), None);
+    }
+
+    #[test]
+    fn routes_events_by_source() {
+        assert_eq!(handle(router(), Some(SQS)).unwrap(), "sqs Test message.");
+        assert_eq!(
+            handle(router(), Some(SNS)).unwrap(),
+            r#"sns "Hello from SNS!""#
+        );
+        assert_eq!(handle(router(), Some(S3)).unwrap(), r#"s3 "test/key""#);
+        assert_eq!(handle(router(), Some(SCHEDULED)).unwrap(), "scheduled");
+        assert_eq!(handle(router(), Some(HTTP)).unwrap(), r#"http "/orders""#);
+    }
+
+    #[test]
+    fn evaluates_user_rules_before_the_built_in_rules() {
+        let router = router()
+            .with_rule(|event| {
+                event
+                    .get("requestContext")
+                    .and_then(|context| context.get("connectionId"))
+                    .map(|_| EventSource::Custom("websocket".to_string()))
+            })
+            .with_route(
+                EventSource::Custom("websocket".to_string()),
+                |_: Value, _| Ok("websocket".to_string()),
+            );
+        assert_eq!(
+            router.detect(&json!({ "requestContext": { "connectionId": "L0SM9cOFvHcCIhw=" } })),
+            Some(EventSource::Custom("websocket".to_string()))
+        );
+        assert_eq!(
+            router.detect(&serde_json::from_str(HTTP).unwrap()),
+            Some(EventSource::Http)
+        );
+        assert_eq!(
+            handle(
+                router,
+                Some(r#"{"requestContext": {"connectionId": "L0SM9cOFvHcCIhw="}}"#)
+            )
+            .unwrap(),
+            "websocket"
+        );
+    }
+
+    #[test]
+    fn falls_back_for_unknown_events() {
+        let fallback = || {
+            router().with_fallback(Box::new(|event, _| {
+                Ok(format!("fallback {}", event.unwrap_or("none")))
+            }))
+        };
+        assert_eq!(
+            handle(fallback(), Some(r#"{"orderId": 42}"#)).unwrap(),
+            r#"fallback {"orderId": 42}"#
+        );
+        // Detected sources without a route, and events that aren't JSON, are passed to the fallback as well.
+        let logs = r#"{"awslogs": {"data": "H4sI"}}"#;
+        assert_eq!(
+            handle(fallback(), Some(logs)).unwrap(),
+            format!("fallback {}", logs)
+        );
+        assert_eq!(
+            handle(fallback(), Some("not json")).unwrap(),
+            "fallback not json"
+        );
+        assert_eq!(handle(fallback(), None).unwrap(), "fallback none");
+
+        let error = handle(router(), Some(r#"{"orderId": 42}"#)).unwrap_err();
+        assert_eq!(error.to_string(), "No handler is routed for the event");
+    }
+
+    #[test]
+    fn fails_on_events_that_do_not_deserialize_into_the_route_type() {
+        let error = handle(
+            router(),
+            Some(r#"{"Records": [{"eventSource": "aws:sqs", "body": 42}]}"#),
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Failed deserializing event from JSON."));
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::error::Error;
+use crate::runtime::stats::time_deserialize;
+
+use std::future::Future;
+#[cfg(not(feature = "tokio"))]
+use std::sync::Arc;
+#[cfg(not(feature = "tokio"))]
+use std::task::{Context, Poll, Wake, Waker};
+#[cfg(not(feature = "tokio"))]
+use std::thread::{self, Thread};
+
+use serde::de::DeserializeOwned;
+
+/// Deserializes an event from JSON into `IN`.
+///
+/// An empty event is deserialized from `null`, so it is accepted by handlers of `Option<T>` and `()` events.
+/// The time it takes is recorded as the [`crate::runtime::stats::Timings::deserialize`] phase of the invocation.
+pub fn deserialize_event<IN: DeserializeOwned>(event: Option<&str>) -> Result<IN, Error> {
+    time_deserialize(|| serde_json::from_str::<IN>(event.unwrap_or("null")))
+        .map_err(|err| Error::new(format!("Failed deserializing event from JSON. {}", err)))
+}
+
+/// Drives a future to completion on a current-thread `tokio` runtime, created once per thread and reused across invocations.
+///
+/// Futures may therefore depend on the `tokio` reactor and timers, e.g. `tower::timeout::Timeout`, `tokio::time::sleep` and `tokio` based HTTP clients.
+#[cfg(feature = "tokio")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    thread_local! {
+        static RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed creating the tokio runtime");
+    }
+    RUNTIME.with(|runtime| runtime.block_on(future))
+}
+
+/// Wakes a parked thread.
+#[cfg(not(feature = "tokio"))]
+struct ThreadWaker(Thread);
+
+#[cfg(not(feature = "tokio"))]
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drives a future to completion on the current thread, parking it while the future is pending.
+///
+/// Used to call async handlers, which may await futures that are woken by other threads,
+/// but not futures that depend on a specific async runtime - awaiting `tokio` timers or IO panics since there is no reactor running.
+/// Enable the `tokio` feature to run the futures on a `tokio` runtime instead.
+#[cfg(not(feature = "tokio"))]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::config::{ConfigError, LambdaConfig};
+use crate::data::context::LambdaContext;
+use crate::data::env::{InitializationType, RuntimeEnvVars};
+use crate::error::{Error, ErrorRequest};
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// The maximal duration of the init phase of an on-demand execution environment.
+/// See [Lambda execution environment lifecycle](https://docs.aws.amazon.com/lambda/latest/dg/lambda-runtime-environment.html#runtimes-lifecycle-ib).
+pub const INIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A hook called with the context of each invocation.
+pub type InvocationHook = Box<dyn FnMut(&dyn LambdaContext)>;
+
+/// A hook called once when the execution environment shuts down.
+pub type ShutdownHook = Box<dyn FnOnce() + Send>;
+
+/// A hook called once before a SnapStart snapshot is taken or after it is restored, that may fail with an error message.
+pub type SnapStartHook = Box<dyn FnOnce() -> Result<(), String>>;
+
+/// Boxes a fallible SnapStart hook, converting its error to a message.
+pub(crate) fn snap_start_hook<F, E>(hook: F) -> SnapStartHook
+where
+    F: FnOnce() -> Result<(), E> + 'static,
+    E: Display,
+{
+    Box::new(move || hook().map_err(|err| err.to_string()))
+}
+
+/// The hooks registered by the initializer, installed by the runtime once the initializer succeeds.
+#[derive(Default)]
+pub(crate) struct InitHooks {
+    pub(crate) shutdown: Vec<ShutdownHook>,
+    pub(crate) before_invocation: Vec<InvocationHook>,
+    pub(crate) after_invocation: Vec<InvocationHook>,
+    pub(crate) before_snapshot: Vec<SnapStartHook>,
+    pub(crate) after_restore: Vec<SnapStartHook>,
+}
+
+/// The context object passed to the initializer of [`crate::runtime::DefaultRuntime`].
+///
+/// It exposes the env-vars already read by the runtime and the time left for initialization,
+/// and lets the initializer register hooks and customize the error reported if initialization fails.
+pub struct InitContext<'a, E: RuntimeEnvVars> {
+    env_vars: &'a E,
+    started: Instant,
+    budget: Duration,
+    lazy: bool,
+    pub(crate) error: Option<ErrorRequest>,
+    pub(crate) hooks: InitHooks,
+}
+
+impl<'a, E: RuntimeEnvVars> InitContext<'a, E> {
+    /// Creates an init context for an init phase that started at `started`.
+    pub fn new(env_vars: &'a E, started: Instant) -> Self {
+        Self {
+            env_vars,
+            started,
+            budget: INIT_TIMEOUT,
+            lazy: false,
+            error: None,
+            hooks: InitHooks::default(),
+        }
+    }
+
+    /// Creates an init context for a lazy initialization during the first invocation, which must complete within `budget`.
+    pub(crate) fn lazy(env_vars: &'a E, started: Instant, budget: Duration) -> Self {
+        Self {
+            budget,
+            lazy: true,
+            ..Self::new(env_vars, started)
+        }
+    }
+
+    #[inline(always)]
+    pub fn env_vars(&self) -> &E {
+        self.env_vars
+    }
+
+    #[inline(always)]
+    pub fn initialization_type(&self) -> InitializationType {
+        self.env_vars.get_initialization_type()
+    }
+
+    /// Returns `true` if the initializer runs during the first invocation instead of the init phase,
+    /// see [`crate::runtime::DefaultRuntime::enable_lazy_init`].
+    #[inline(always)]
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    /// Returns the time elapsed since the init phase started.
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Returns the time left until the [`INIT_TIMEOUT`] of on-demand execution environments elapses,
+    /// measured from the creation of the runtime.
+    /// When initializing lazily, returns the time left until the deadline of the first invocation.
+    #[inline]
+    pub fn remaining_time(&self) -> Duration {
+        self.budget.saturating_sub(self.elapsed())
+    }
+
+    /// Loads a [`LambdaConfig`] from the env-vars already read by the runtime, using [`RuntimeEnvVars::get_other`].
+    ///
+    /// If loading fails, the aggregated error is also set as the error reported to the Lambda service if the initializer fails,
+    /// with a [`crate::config::CONFIG_ERR_TYPE`] error type.
+    pub fn load_config<C: LambdaConfig>(&mut self) -> Result<C, ConfigError> {
+        let env_vars = self.env_vars;
+        C::from_vars(|name| env_vars.get_other(name).map(str::to_string))
+            .inspect_err(|err| self.set_error(err.clone().into()))
+    }
+
+    /// Sets the error reported to the Lambda service if the initializer fails,
+    /// instead of a `Runtime.InitError` with the message of the returned error.
+    pub fn set_error(&mut self, error: ErrorRequest) {
+        self.error = Some(error);
+    }
+
+    /// Registers a hook that is called when the execution environment shuts down.
+    ///
+    /// AWS Lambda only signals the shutdown - by sending `SIGTERM` - to functions that use external or internal extensions.
+    /// On receiving the signal the hooks are called in the order they were registered, from a dedicated thread, and the process exits.
+    /// Shutdown hooks are currently only supported on unix platforms.
+    pub fn on_shutdown<F: FnOnce() + Send + 'static>(&mut self, hook: F) {
+        self.hooks.shutdown.push(Box::new(hook));
+    }
+
+    /// Registers a hook that is called with the context of each invocation, before the event handler.
+    pub fn before_invocation<F: FnMut(&dyn LambdaContext) + 'static>(&mut self, hook: F) {
+        self.hooks.before_invocation.push(Box::new(hook));
+    }
+
+    /// Registers a hook that is called with the context of each invocation, after the handler's result was sent.
+    pub fn after_invocation<F: FnMut(&dyn LambdaContext) + 'static>(&mut self, hook: F) {
+        self.hooks.after_invocation.push(Box::new(hook));
+    }
+
+    /// Registers a hook that is called before a SnapStart snapshot of the execution environment is taken,
+    /// after the hooks registered using [`crate::runtime::DefaultRuntime::before_snapshot`].
+    ///
+    /// Only called when the initialization type is [`InitializationType::SnapStart`].
+    /// A failing hook is reported as a `Runtime.BeforeSnapshotError` init error.
+    pub fn before_snapshot<F, ERR>(&mut self, hook: F)
+    where
+        F: FnOnce() -> Result<(), ERR> + 'static,
+        ERR: Display,
+    {
+        self.hooks.before_snapshot.push(snap_start_hook(hook));
+    }
+
+    /// Registers a hook that is called after the execution environment is restored from a SnapStart snapshot,
+    /// after the hooks registered using [`crate::runtime::DefaultRuntime::after_restore`].
+    ///
+    /// Use it to refresh state that must be unique to each execution environment or that may be stale after restoring -
+    /// connections, cached credentials and random seeds.
+    /// A failing hook is reported as a `Runtime.AfterRestoreError` restore error.
+    pub fn after_restore<F, ERR>(&mut self, hook: F)
+    where
+        F: FnOnce() -> Result<(), ERR> + 'static,
+        ERR: Display,
+    {
+        self.hooks.after_restore.push(snap_start_hook(hook));
+    }
+}
+
+/// Calls `hooks` and exits the process when it receives `SIGTERM`.
+#[cfg(unix)]
+pub(crate) fn install_shutdown_hooks(hooks: Vec<ShutdownHook>) -> Result<(), Error> {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// The write end of the pipe used to notify the hooks thread from the signal handler.
+    static NOTIFY_FD: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_sigterm(_signal: libc::c_int) {
+        let fd = NOTIFY_FD.load(Ordering::SeqCst);
+        if fd >= 0 {
+            // Only async-signal-safe calls are allowed here.
+            unsafe {
+                libc::write(fd, [1u8].as_ptr() as *const libc::c_void, 1);
+            }
+        }
+    }
+
+    let mut fds = [0 as libc::c_int; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(Error::new(format!(
+            "Failed creating shutdown pipe. {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    let [read_fd, write_fd] = fds;
+    NOTIFY_FD.store(write_fd, Ordering::SeqCst);
+
+    std::thread::Builder::new()
+        .name("rtlambda-shutdown".to_string())
+        .spawn(move || {
+            let mut buf = [0u8; 1];
+            while unsafe { libc::read(read_fd, buf.as_mut_ptr() as *mut libc::c_void, 1) } != 1 {}
+            for hook in hooks {
+                hook();
+            }
+            std::process::exit(0);
+        })
+        .map_err(|err| Error::new(format!("Failed spawning the shutdown thread. {}", err)))?;
+
+    let handler = on_sigterm as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGTERM, handler) } == libc::SIG_ERR {
+        return Err(Error::new(format!(
+            "Failed installing SIGTERM handler. {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn install_shutdown_hooks(_hooks: Vec<ShutdownHook>) -> Result<(), Error> {
+    Err(Error::new(
+        "Shutdown hooks are only supported on unix platforms".to_string(),
+    ))
+}
@@ -2,15 +2,34 @@
 
 // `SPDX-License-Identifier: MIT OR Apache-2.0`
 
-use crate::data::context::RefLambdaContext;
-use crate::data::env::RuntimeEnvVars;
-use crate::data::response::{LambdaAPIResponse, AWS_FUNC_ERR_TYPE};
-use crate::error::{Error, CONTAINER_ERR};
+/// Helpers used by the entry points generated by the `#[rtlambda::main]` attribute macro.
+pub mod entry;
+/// The context object passed to the initializer.
+pub mod init;
+/// A registry of named initializers, selected by the handler configured for the function.
+pub mod registry;
+/// Timings and statistics collected by the runtime.
+pub mod stats;
+/// A watchdog that reports invocations that are about to time out.
+pub mod watchdog;
+
+use crate::data::context::{
+    ContextEnv, ContextKind, OwnedLambdaContext, RefContextKind, RefLambdaContext,
+};
+use crate::data::env::{InitializationType, RuntimeEnvVars};
+use crate::data::response::{LambdaAPIResponse, AWS_FUNC_ERR_TYPE, AWS_FUNC_XRAY_ERR_CAUSE};
+use crate::error::{Error, ErrorRequest, CONTAINER_ERR, MAX_XRAY_ERROR_CAUSE_SIZE};
 use crate::transport::Transport;
+use init::{InitContext, InvocationHook, SnapStartHook};
+use registry::{HandlerOutput, HandlerRegistry};
+use stats::{StatsHandle, Timings};
+use watchdog::{Watchdog, WatchdogConfig};
 
 use std::env::set_var;
 use std::ffi::OsStr;
 use std::fmt::Display;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use serde::Serialize;
 
@@ -42,6 +61,46 @@ macro_rules! format_version_string {
     };
 }
 
+/// Locks a mutex, ignoring poisoning since the data guarded by the runtime's mutexes is always left in a consistent state.
+pub(crate) fn lock<D>(mutex: &Mutex<D>) -> MutexGuard<'_, D> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A boxed event handler closure, accepting the event (as an optional string) and a context object.
+///
+/// The type of the context object is selected by `CTX`, see [`crate::data::context::ContextKind`].
+pub type EventHandler<ENV, R, OUT, ERR, CTX = RefContextKind> = Box<
+    dyn for<'a> Fn(Option<&'a str>, <CTX as ContextKind<ENV, R>>::Context<'a>) -> Result<OUT, ERR>,
+>;
+
+/// An [`EventHandler`] accepting an [`crate::data::context::OwnedLambdaContext`].
+///
+/// Since the owned context does not borrow from the runtime, this type is the same for any `ENV` and `R` types
+/// used with [`crate::data::context::OwnedContextKind`].
+pub type OwnedEventHandler<OUT, ERR> =
+    Box<dyn Fn(Option<&str>, OwnedLambdaContext) -> Result<OUT, ERR>>;
+
+/// A pointer to an initialization function that accepts an [`InitContext`] and returns an [`EventHandler`] or an error.
+pub type Initializer<ENV, R, OUT, ERR, CTX = RefContextKind> =
+    fn(&mut InitContext<ENV>) -> Result<EventHandler<ENV, R, OUT, ERR, CTX>, ERR>;
+
+/// A boxed initializer, returning the error to report to the Lambda service if it fails.
+type BoxedInitializer<ENV, R, OUT, ERR, CTX> =
+    Box<dyn Fn(&mut InitContext<ENV>) -> Result<EventHandler<ENV, R, OUT, ERR, CTX>, ErrorRequest>>;
+
+/// The initializer run by [`DefaultRuntime`].
+enum RuntimeInitializer<ENV, R, OUT, ERR, CTX>
+where
+    ENV: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+    CTX: ContextKind<ENV, R>,
+{
+    /// An initialization function.
+    Function(Initializer<ENV, R, OUT, ERR, CTX>),
+    /// The initializer selected from a [`HandlerRegistry`].
+    Registry(BoxedInitializer<ENV, R, OUT, ERR, CTX>),
+}
+
 /// A generic trait defining an interface for a Lambda runtime.
 /// The HTTP Backend in use is defined by the input types `T` that implements [`Transport`] and `R` implementing [`LambdaAPIResponse`].
 /// The `OUT` type parameter is the user-defined response type which represents the success result of the event handler.
@@ -58,18 +117,25 @@ where
     /// Sends back a JSON formatted response to the Lambda service, after processing an event.
     fn invocation_response(&self, request_id: &str, response: &OUT) -> Result<R, Error>;
     /// Used to report an error during initialization to the Lambda service.
-    fn initialization_error(
-        &self,
-        error_type: Option<&str>,
-        error_req: Option<&str>,
-    ) -> Result<R, Error>;
+    fn initialization_error(&self, error: &ErrorRequest) -> Result<R, Error>;
     /// Used to report an error during function invocation to the Lambda service.
-    fn invocation_error(
-        &self,
-        request_id: &str,
-        error_type: Option<&str>,
-        error_req: Option<&str>,
-    ) -> Result<R, Error>;
+    fn invocation_error(&self, request_id: &str, error: &ErrorRequest) -> Result<R, Error>;
+    /// Used to signal that a SnapStart snapshot may be taken, returns after the snapshot is restored.
+    ///
+    /// Defaults to returning an error, for runtimes that don't support SnapStart.
+    fn restore_next(&mut self) -> Result<R, Error> {
+        Err(Error::new(
+            "SnapStart is not supported by the runtime".to_string(),
+        ))
+    }
+    /// Used to report an error after restoring a SnapStart snapshot to the Lambda service.
+    ///
+    /// Defaults to returning an error, for runtimes that don't support SnapStart.
+    fn restore_error(&self, _error: &ErrorRequest) -> Result<R, Error> {
+        Err(Error::new(
+            "SnapStart is not supported by the runtime".to_string(),
+        ))
+    }
     /// Implements the runtime loop logic.
     fn run(&mut self);
 }
@@ -88,12 +154,15 @@ where
 /// The `ENV` type parameter defines the implementation of [`crate::data::env::RuntimeEnvVars`] for reading the env-vars set for the runtime.
 ///
 /// The `ERR` type parameter is a user-defined type representing any error that may occur during initialization or invocation of the event handler.
-pub struct DefaultRuntime<R, T, ENV, OUT, ERR>
+///
+/// The `CTX` type parameter selects the type of the context object passed to the event handler - a borrowing [`RefLambdaContext`] by default,
+/// or an owned [`crate::data::context::OwnedLambdaContext`] using [`crate::data::context::OwnedContextKind`].
+pub struct DefaultRuntime<R, T, ENV, OUT, ERR, CTX = RefContextKind>
 where
     R: LambdaAPIResponse,
     T: Transport<R>,
     ENV: RuntimeEnvVars,
-    //   I: LambdaContext,
+    CTX: ContextKind<ENV, R>,
     ERR: Display,
     OUT: Serialize,
 {
@@ -106,29 +175,81 @@ where
     /// An owned instance of the HTTP Backend implementing [`crate::transport::Transport`].
     transport: T,
     /// An initialization function that sets up persistent variables and returns the event handler.
-    initializer:
-        fn()
-            -> Result<Box<dyn Fn(Option<&str>, RefLambdaContext<ENV, R>) -> Result<OUT, ERR>>, ERR>,
+    initializer: RuntimeInitializer<ENV, R, OUT, ERR, CTX>,
+    /// Converts the errors of the event handler to the error reported to the Lambda service.
+    error_request: fn(&ERR) -> ErrorRequest,
+    /// The per-runtime part of the context object, shared by all owned context objects.
+    context_env: Arc<ContextEnv>,
+    /// The time the runtime was created, considered as the start of the init phase.
+    created: Instant,
+    /// Hooks registered by the initializer, called before each invocation.
+    before_invocation: Vec<InvocationHook>,
+    /// Hooks registered by the initializer, called after each invocation.
+    after_invocation: Vec<InvocationHook>,
+    /// Hooks called before a SnapStart snapshot is taken.
+    before_snapshot: Vec<SnapStartHook>,
+    /// Hooks called after a SnapStart snapshot is restored.
+    after_restore: Vec<SnapStartHook>,
+    /// Whether to run the initializer during the first invocation of on-demand execution environments.
+    lazy_init: bool,
+    /// The time spent running the initializer, set once it succeeds.
+    init_duration: Duration,
+    /// The safety margin passed to the context object, used when creating a [`crate::data::deadline::DeadlineToken`].
+    deadline_margin: Duration,
+    /// An optional watchdog reporting invocations that are about to time out.
+    watchdog: Option<Watchdog>,
+    /// The statistics collected during the runtime's life.
+    stats: StatsHandle,
+    /// An optional template of the metrics logger used to write the statistics of each invocation.
+    #[cfg(feature = "metrics")]
+    stats_metrics: Option<crate::metrics::MetricsLogger>,
+    /// An optional recorder of X-Ray subsegments.
+    #[cfg(feature = "xray")]
+    xray: Option<crate::xray::Recorder>,
+    /// An optional recorder of OpenTelemetry invocation spans.
+    #[cfg(feature = "opentelemetry")]
+    opentelemetry: Option<crate::opentelemetry::Instrumentation>,
+    /// An optional template of the metrics logger of each invocation.
+    #[cfg(feature = "metrics")]
+    metrics: Option<crate::metrics::MetricsLogger>,
 }
 
-impl<R, T, ENV, OUT, ERR> DefaultRuntime<R, T, ENV, OUT, ERR>
+impl<R, T, ENV, OUT, ERR, CTX> DefaultRuntime<R, T, ENV, OUT, ERR, CTX>
 where
     R: LambdaAPIResponse,
     T: Transport<R>,
     ENV: RuntimeEnvVars,
-    //   I: LambdaContext,
+    CTX: ContextKind<ENV, R>,
     ERR: Display,
     OUT: Serialize,
 {
-    pub fn new(
+    pub fn new(version: &str, initializer: Initializer<ENV, R, OUT, ERR, CTX>) -> Self {
+        Self::from_env_vars(version, ENV::default(), initializer)
+    }
+
+    /// Creates a runtime reading the env-vars from `env_vars` instead of the default `ENV`,
+    /// e.g. a [`crate::data::env::LambdaRuntimeEnv`] built for running against an emulator.
+    pub fn from_env_vars(
+        version: &str,
+        env_vars: ENV,
+        initializer: Initializer<ENV, R, OUT, ERR, CTX>,
+    ) -> Self {
+        Self::create(
+            version,
+            env_vars,
+            RuntimeInitializer::Function(initializer),
+            ErrorRequest::from_error,
+        )
+    }
+
+    fn create(
         version: &str,
-        initializer: fn() -> Result<
-            Box<dyn Fn(Option<&str>, RefLambdaContext<ENV, R>) -> Result<OUT, ERR>>,
-            ERR,
-        >,
+        env_vars: ENV,
+        initializer: RuntimeInitializer<ENV, R, OUT, ERR, CTX>,
+        error_request: fn(&ERR) -> ErrorRequest,
     ) -> Self {
-        // Initialize default env vars and check for the host and port of the runtime API.
-        let env_vars = ENV::default();
+        let created = Instant::now();
+        // Check for the host and port of the runtime API.
         let api_base = match env_vars.get_runtime_api() {
             Some(v) => v.to_string(),
             None => panic!("Failed getting API base URL from env vars"),
@@ -140,12 +261,34 @@ where
         // Start the transport layer object
         let transport = T::default();
 
+        let context_env = Arc::new(ContextEnv::from_env_vars(&env_vars));
+
         Self {
             env_vars,
             version: formatted_version,
             api_base,
             transport,
             initializer,
+            error_request,
+            context_env,
+            created,
+            before_invocation: Vec::new(),
+            after_invocation: Vec::new(),
+            before_snapshot: Vec::new(),
+            after_restore: Vec::new(),
+            lazy_init: false,
+            init_duration: Duration::ZERO,
+            deadline_margin: Duration::ZERO,
+            watchdog: None,
+            stats: StatsHandle::default(),
+            #[cfg(feature = "metrics")]
+            stats_metrics: None,
+            #[cfg(feature = "xray")]
+            xray: None,
+            #[cfg(feature = "opentelemetry")]
+            opentelemetry: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
         }
     }
 
@@ -153,44 +296,332 @@ where
     pub fn get_env(&self) -> &ENV {
         &self.env_vars
     }
+
+    /// Returns a handle to the statistics collected by the runtime, which is updated after each invocation.
+    #[inline]
+    pub fn get_stats(&self) -> StatsHandle {
+        self.stats.clone()
+    }
+
+    /// Returns the safety margin passed to the context object of each invocation.
+    #[inline(always)]
+    pub fn get_deadline_margin(&self) -> Duration {
+        self.deadline_margin
+    }
+
+    /// Enables recording an X-Ray subsegment for each sampled invocation, sent to the daemon address set in the `AWS_XRAY_DAEMON_ADDRESS` env-var.
+    /// Event handlers can record nested subsegments using [`crate::xray::begin_subsegment`].
+    #[cfg(feature = "xray")]
+    pub fn enable_xray(&mut self) -> Result<(), Error> {
+        let emitter = match self.env_vars.get_xray_daemon_address() {
+            Some(address) => crate::xray::Emitter::new(address)?,
+            None => {
+                return Err(Error::new(format!(
+                    "{} is not set",
+                    crate::xray::XRAY_DAEMON_ADDRESS
+                )))
+            }
+        };
+        self.xray = Some(crate::xray::Recorder::new(emitter));
+        Ok(())
+    }
+
+    /// Enables recording an OpenTelemetry server span for each invocation, exported by the span processors of `provider`.
+    /// The provider is flushed before polling for the next event.
+    ///
+    /// The provider is where the exporter is configured, e.g. using `SdkTracerProvider::builder().with_simple_exporter(exporter)`.
+    /// Event handlers can record nested spans using any tracer created by `provider`.
+    #[cfg(feature = "opentelemetry")]
+    pub fn enable_opentelemetry(&mut self, provider: opentelemetry_sdk::trace::SdkTracerProvider) {
+        self.opentelemetry = Some(crate::opentelemetry::Instrumentation::new(provider));
+    }
+
+    /// Enables collecting metrics during each invocation, written to stdout in the CloudWatch Embedded Metric Format after the handler returns.
+    /// Each invocation starts with a copy of `metrics`, which holds the namespace, dimensions and properties common to all invocations.
+    ///
+    /// Event handlers can record metrics using [`crate::metrics::put_metric`] and [`crate::metrics::with_current`].
+    #[cfg(feature = "metrics")]
+    pub fn enable_metrics(&mut self, metrics: crate::metrics::MetricsLogger) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Enables writing the timings of each invocation - see [`stats::Timings`] - in the CloudWatch Embedded Metric Format,
+    /// using a copy of `metrics` which holds the namespace, dimensions and properties of the written metrics.
+    ///
+    /// The metrics are `ColdStart`, `InitDuration` - for cold starts - and the `NextWait`, `Deserialize`, `Handler`,
+    /// `Serialize` and `Acknowledge` phases in milliseconds.
+    #[cfg(feature = "metrics")]
+    pub fn enable_stats_metrics(&mut self, metrics: crate::metrics::MetricsLogger) {
+        self.stats_metrics = Some(metrics);
+    }
+
+    /// Registers a hook that is called before a SnapStart snapshot of the execution environment is taken,
+    /// once the initializer has returned.
+    ///
+    /// Only called when the initialization type is [`InitializationType::SnapStart`].
+    /// A failing hook is reported as a `Runtime.BeforeSnapshotError` init error.
+    pub fn before_snapshot<F, E>(&mut self, hook: F)
+    where
+        F: FnOnce() -> Result<(), E> + 'static,
+        E: Display,
+    {
+        self.before_snapshot.push(init::snap_start_hook(hook));
+    }
+
+    /// Registers a hook that is called after the execution environment is restored from a SnapStart snapshot.
+    ///
+    /// Before calling the hooks the runtime reseeds the generator of trace and segment ids and reconnects to the runtime API.
+    /// Any connections, cached credentials or random seeds created during initialization should be refreshed by a hook.
+    /// A failing hook is reported as a `Runtime.AfterRestoreError` restore error.
+    pub fn after_restore<F, E>(&mut self, hook: F)
+    where
+        F: FnOnce() -> Result<(), E> + 'static,
+        E: Display,
+    {
+        self.after_restore.push(init::snap_start_hook(hook));
+    }
+
+    /// Defers running the initializer to the first invocation, when the initialization type is [`InitializationType::OnDemand`].
+    ///
+    /// This keeps expensive setup out of the time-limited init phase, while provisioned concurrency and SnapStart environments
+    /// are still initialized eagerly. When initializing lazily, a failing initializer is reported as an invocation error
+    /// and is run again on the next invocation.
+    pub fn enable_lazy_init(&mut self) {
+        self.lazy_init = true;
+    }
+
+    /// Sets the safety margin passed to the context object of each invocation.
+    /// A [`crate::data::deadline::DeadlineToken`] created from the context expires `margin` before the actual deadline.
+    #[inline]
+    pub fn set_deadline_margin(&mut self, margin: Duration) {
+        self.deadline_margin = margin;
+    }
+
+    /// Sets the function converting the errors of the event handler to the error reported to the Lambda service.
+    ///
+    /// Defaults to [`ErrorRequest::from_error`], reporting the name of the error type as the error type.
+    /// Replace it to report a more specific error type, e.g. one per error variant.
+    ///
+    /// The runtime doesn't capture a stack trace, since it would only show the runtime's own frames.
+    /// To report where an error was created, capture a [`std::backtrace::Backtrace`] along with the error
+    /// and attach it in this function using [`ErrorRequest::with_backtrace`].
+    #[inline]
+    pub fn set_error_request(&mut self, error_request: fn(&ERR) -> ErrorRequest) {
+        self.error_request = error_request;
+    }
+
+    fn serialize_response(response: &OUT) -> Result<String, Error> {
+        // TODO - Utilize a user-defined JSON serializer?
+        serde_json::to_string(response)
+            .map_err(|err| Error::new(format!("Failed serializing output to JSON. {}", err)))
+    }
+
+    /// Runs the initializer and installs the hooks it registered, returning the event handler.
+    /// If the initializer fails, returns the error to report to the Lambda service.
+    ///
+    /// `lazy_budget` is the time left for a lazy initialization, or `None` when initializing during the init phase.
+    fn initialize(
+        &mut self,
+        lazy_budget: Option<Duration>,
+    ) -> Result<EventHandler<ENV, R, OUT, ERR, CTX>, ErrorRequest> {
+        let init_start = Instant::now();
+        let mut init_context = match lazy_budget {
+            Some(budget) => InitContext::lazy(&self.env_vars, init_start, budget),
+            None => InitContext::new(&self.env_vars, self.created),
+        };
+        let init_result = match &self.initializer {
+            RuntimeInitializer::Function(initializer) => {
+                initializer(&mut init_context).map_err(|init_err| {
+                    init_context.error.take().unwrap_or_else(|| {
+                        ErrorRequest::new("Runtime.InitError", &init_err.to_string())
+                    })
+                })
+            }
+            RuntimeInitializer::Registry(initializer) => initializer(&mut init_context),
+        };
+        let init_duration = init_start.elapsed();
+        let event_handler = init_result?;
+        let hooks = init_context.hooks;
+
+        self.init_duration = init_duration;
+        self.stats.set_init_duration(init_duration);
+        self.before_invocation.extend(hooks.before_invocation);
+        self.after_invocation.extend(hooks.after_invocation);
+        self.before_snapshot.extend(hooks.before_snapshot);
+        self.after_restore.extend(hooks.after_restore);
+        if !hooks.shutdown.is_empty() {
+            if let Err(err) = init::install_shutdown_hooks(hooks.shutdown) {
+                eprintln!("{}", err);
+            }
+        }
+        Ok(event_handler)
+    }
+
+    /// Runs the before-snapshot hooks, waits for the snapshot to be restored and then runs the after-restore hooks.
+    /// Returns the error reported to the Lambda service if a hook fails.
+    fn snap_start(&mut self) -> Result<(), ErrorRequest> {
+        for hook in std::mem::take(&mut self.before_snapshot) {
+            if let Err(msg) = hook() {
+                let error = ErrorRequest::new("Runtime.BeforeSnapshotError", &msg);
+                if let Err(err) = self.initialization_error(&error) {
+                    eprintln!("Failed to report before-snapshot error. {}", err);
+                }
+                return Err(error);
+            }
+        }
+
+        if let Err(err) = self.restore_next() {
+            return Err(ErrorRequest::new("Runtime.RestoreError", &err.to_string()));
+        }
+
+        // Everything in memory is shared by all the execution environments restored from the snapshot.
+        crate::data::trace::reseed_random();
+        // The credentials of the execution environment in which the snapshot was taken may no longer be valid.
+        crate::credentials::invalidate_cached();
+        // Connections opened before the snapshot was taken are no longer valid.
+        self.transport = T::default();
+
+        for hook in std::mem::take(&mut self.after_restore) {
+            if let Err(msg) = hook() {
+                let error = ErrorRequest::new("Runtime.AfterRestoreError", &msg);
+                if let Err(err) = self.restore_error(&error) {
+                    eprintln!("Failed to report after-restore error. {}", err);
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends back an already serialized response to the Lambda service.
+    fn post_response(&self, request_id: &str, serialized: &str) -> Result<R, Error> {
+        let url = format!(
+            "http://{}/{}/runtime/invocation/{}/response",
+            self.api_base, self.version, request_id
+        );
+        let resp = self.transport.post(&url, Some(serialized), None)?;
+
+        handle_response!(resp);
+
+        Ok(resp)
+    }
+
+    /// Marks the start of a new phase of the current invocation, if the watchdog is enabled.
+    #[inline]
+    fn watchdog_phase(&self, name: &'static str) {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.phase(name);
+        }
+    }
+}
+
+impl<R, T, ENV, OUT, ERR, CTX> DefaultRuntime<R, T, ENV, OUT, ERR, CTX>
+where
+    R: LambdaAPIResponse + 'static,
+    T: Transport<R> + 'static,
+    ENV: RuntimeEnvVars,
+    CTX: ContextKind<ENV, R>,
+    ERR: Display,
+    OUT: Serialize,
+{
+    /// Starts a [`Watchdog`] thread that reports a [`watchdog::TIMEOUT_ERR_TYPE`] invocation error
+    /// shortly before the deadline of an invocation, and optionally exits the process.
+    ///
+    /// The watchdog uses its own instance of the transport `T`.
+    /// Should be called before [`LambdaRuntime::run`], calling it again replaces the previous watchdog's configuration.
+    pub fn enable_watchdog(&mut self, config: WatchdogConfig) {
+        let api_url = format!("http://{}/{}", self.api_base, self.version);
+        self.watchdog = Some(Watchdog::spawn::<R, T>(config, api_url));
+    }
+}
+
+impl<R, T, ENV, CTX> DefaultRuntime<R, T, ENV, HandlerOutput, ErrorRequest, CTX>
+where
+    R: LambdaAPIResponse + 'static,
+    T: Transport<R>,
+    ENV: RuntimeEnvVars + 'static,
+    CTX: ContextKind<ENV, R> + 'static,
+{
+    /// Creates a runtime that runs the initializer registered in `registry` under the name set in the `_HANDLER` env-var.
+    /// See [`HandlerRegistry`].
+    pub fn from_registry(version: &str, registry: HandlerRegistry<ENV, R, CTX>) -> Self {
+        Self::from_registry_with_env_vars(version, ENV::default(), registry)
+    }
+
+    /// Creates a runtime that runs the initializer registered in `registry`, reading the env-vars from `env_vars`
+    /// instead of the default `ENV`. See [`DefaultRuntime::from_registry`].
+    pub fn from_registry_with_env_vars(
+        version: &str,
+        env_vars: ENV,
+        registry: HandlerRegistry<ENV, R, CTX>,
+    ) -> Self {
+        Self::create(
+            version,
+            env_vars,
+            RuntimeInitializer::Registry(Box::new(move |init_context| {
+                registry.initialize(init_context)
+            })),
+            ErrorRequest::clone,
+        )
+    }
 }
 
-impl<R, T, ENV, OUT, ERR> LambdaRuntime<R, T, OUT> for DefaultRuntime<R, T, ENV, OUT, ERR>
+impl<R, T, ENV, OUT, ERR, CTX> LambdaRuntime<R, T, OUT> for DefaultRuntime<R, T, ENV, OUT, ERR, CTX>
 where
     R: LambdaAPIResponse,
     T: Transport<R>,
     ENV: RuntimeEnvVars,
-    // I: LambdaContext,
+    CTX: ContextKind<ENV, R>,
     ERR: Display,
     OUT: Serialize,
 {
     fn run(&mut self) {
-        // Run the app's initializer and check for errors
-        let init_result = (self.initializer)();
-        let lambda = match init_result {
-            Err(init_err) => {
-                // Try reporting to the Lambda service if there is an error during initialization
-                // TODO: Take error type and request from ERR
-                match self.initialization_error(Some("Runtime.InitError"), None) {
-                    Ok(r) => r,
-                    // If an error occurs during reporting the previous error, panic.
-                    Err(err) => panic!(
-                        "Failed to report initialization error. Error: {}, AWS Error: {}",
-                        &init_err, err
-                    ),
-                };
-                // After reporting an init error just panic.
-                panic!("Initialization Error: {}", &init_err);
+        let lazy = self.lazy_init
+            && self.env_vars.get_initialization_type() == InitializationType::OnDemand;
+        let mut lambda = None;
+        if !lazy {
+            // Run the app's initializer and check for errors
+            match self.initialize(None) {
+                // On successfull init, keep the underlying closure (event handler)
+                Ok(event_handler) => lambda = Some(event_handler),
+                Err(error) => {
+                    // Try reporting to the Lambda service if there is an error during initialization
+                    if let Err(err) = self.initialization_error(&error) {
+                        // If an error occurs during reporting the previous error, panic.
+                        panic!(
+                            "Failed to report initialization error. Error: {}, AWS Error: {}",
+                            &error, err
+                        );
+                    }
+                    // After reporting an init error just panic.
+                    panic!("Initialization Error: {}", &error);
+                }
             }
-            // On successfull init, unwrap the underlying closure (event handler)
-            Ok(event_handler) => event_handler,
-        };
+        }
+        if self.env_vars.get_initialization_type() == InitializationType::SnapStart {
+            // The Lambda service fails the restore, there is nothing left to do.
+            if let Err(error) = self.snap_start() {
+                panic!("SnapStart Error: {}", error);
+            }
+        }
+        let mut invocation_index: u64 = 0;
 
         // Start event processing loop as specified in [https://docs.aws.amazon.com/lambda/latest/dg/runtimes-custom.html]
         loop {
+            #[cfg(feature = "log")]
+            crate::log::set_request_id(None);
+            // Export the spans of the previous invocation, since the environment may be frozen while waiting for the next event.
+            #[cfg(feature = "opentelemetry")]
+            if let Some(opentelemetry) = &self.opentelemetry {
+                opentelemetry.flush();
+            }
+
             // Get the next event in the queue.
             // Failing to get the next event will either panic (on server error) or continue (on client-error codes).
+            let mut timings = Timings::default();
+            let phase_start = Instant::now();
             let next: Result<R, _> = self.next_invocation();
+            timings.next_wait = phase_start.elapsed();
             if next.is_err() {
                 // TODO - perhaps log the error
                 continue;
@@ -200,33 +631,149 @@ where
                 Some(rid) => rid,
                 None => {
                     // TODO - figure out what we'd like to do with the result returned from success/client-err api responses
-                    let _ = self.initialization_error(Some("Runtime.MissingRequestId"), None);
+                    let error = ErrorRequest::new(
+                        "Runtime.MissingRequestId",
+                        "The next invocation has no request id",
+                    );
+                    let _ = self.initialization_error(&error);
                     continue;
                 }
             };
 
-            // Create the context object for the lambda execution
-            // TODO - Design a way to pass a generic type implementing LambdaContext and use it to construct the context
-            let context = RefLambdaContext {
-                env_vars: &self.env_vars,
-                invo_resp: next_resp,
+            #[cfg(feature = "log")]
+            crate::log::set_request_id(Some(request_id));
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                crate::metrics::begin_invocation(metrics, request_id);
+            }
+            if let Some(watchdog) = &self.watchdog {
+                watchdog.begin(request_id, next_resp.deadline());
+            }
+            #[cfg(feature = "xray")]
+            if let Some(xray) = &mut self.xray {
+                xray.begin_invocation(next_resp.trace_id());
+            }
+
+            // Initialize lazily during the first invocation, the initializer is run again on the next invocation if it fails.
+            let event_handler = match lambda.take() {
+                Some(event_handler) => Ok(event_handler),
+                None => {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    let budget = next_resp
+                        .deadline()
+                        .and_then(|deadline| deadline.checked_sub(now))
+                        .unwrap_or_default();
+                    self.initialize(Some(budget))
+                }
             };
-            // Retrieve the event JSON
-            // TODO - deserialize? Currently user code should deserialize inside their handler
+
+            // Create the context object for the lambda execution
+            let context = RefLambdaContext::new(&self.env_vars, next_resp)
+                .with_deadline_margin(self.deadline_margin)
+                .with_invocation_index(invocation_index)
+                .with_init_duration(self.init_duration);
+            #[cfg(feature = "opentelemetry")]
+            let otel_guard = self
+                .opentelemetry
+                .as_mut()
+                .map(|opentelemetry| opentelemetry.begin_invocation(&context));
+            for hook in &mut self.before_invocation {
+                hook(&context);
+            }
+            // Retrieve the event JSON, deserialized by the handler - see `stats::time_deserialize`
             let event = next_resp.event_response();
 
             // Execute the event handler
-            let lambda_output = lambda(event, context);
+            self.watchdog_phase("handler");
+            stats::take_deserialize();
+            let phase_start = Instant::now();
+            let lambda_output = match event_handler {
+                Ok(event_handler) => {
+                    let output = match event_handler(
+                        event,
+                        CTX::from_ref(context.clone(), &self.context_env),
+                    ) {
+                        Ok(out) => Ok(out),
+                        Err(err) => Err((self.error_request)(&err)),
+                    };
+                    lambda = Some(event_handler);
+                    output
+                }
+                Err(error) => Err(error),
+            };
+            timings.deserialize = stats::take_deserialize();
+            timings.handler = phase_start.elapsed().saturating_sub(timings.deserialize);
+            #[cfg(feature = "xray")]
+            if let Some(xray) = &mut self.xray {
+                xray.end_invocation(lambda_output.is_err());
+            }
+            #[cfg(feature = "opentelemetry")]
+            if let Some(opentelemetry) = &mut self.opentelemetry {
+                drop(otel_guard);
+                opentelemetry.end_invocation(
+                    lambda_output
+                        .as_ref()
+                        .err()
+                        .map(|e| e.error_message.clone()),
+                );
+            }
+            #[cfg(feature = "metrics")]
+            crate::metrics::end_invocation();
+
+            // The invocation was already reported as timed out, there is no point in sending the result.
+            let timed_out = match &self.watchdog {
+                Some(watchdog) => watchdog.timed_out(),
+                None => false,
+            };
 
             // TODO - figure out what we'd like to do with the result returned from success/client-err api responses (e.g: log, run a user defined callback...)
-            let _ = match lambda_output {
-                Ok(out) => self.invocation_response(request_id, &out),
-                // TODO - pass an ErrorRequest json
-                Err(err) => {
-                    let _err = format!("{}", &err);
-                    self.invocation_error(request_id, Some(&_err), Some(&_err))
+            if !timed_out {
+                let _ = match lambda_output {
+                    Ok(out) => {
+                        self.watchdog_phase("serialize");
+                        let phase_start = Instant::now();
+                        match Self::serialize_response(&out) {
+                            Ok(serialized) => {
+                                timings.serialize = phase_start.elapsed();
+                                self.watchdog_phase("acknowledge");
+                                let phase_start = Instant::now();
+                                let resp = self.post_response(request_id, &serialized);
+                                timings.acknowledge = phase_start.elapsed();
+                                resp
+                            }
+                            Err(err) => Err(err),
+                        }
+                    }
+                    Err(error) => {
+                        self.watchdog_phase("acknowledge");
+                        let phase_start = Instant::now();
+                        let resp = self.invocation_error(request_id, &error);
+                        timings.acknowledge = phase_start.elapsed();
+                        resp
+                    }
+                };
+            }
+            if let Some(watchdog) = &self.watchdog {
+                watchdog.end();
+            }
+
+            for hook in &mut self.after_invocation {
+                hook(&context);
+            }
+
+            #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+            let invocation = self.stats.record(timings);
+            invocation_index += 1;
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.stats_metrics {
+                if let Err(err) =
+                    stats::write_metrics(metrics, &invocation, Some(self.init_duration))
+                {
+                    eprintln!("{}", err);
                 }
-            };
+            }
         }
     }
 
@@ -250,61 +797,86 @@ where
     }
 
     fn invocation_response(&self, request_id: &str, response: &OUT) -> Result<R, Error> {
+        let serialized = Self::serialize_response(response)?;
+        self.post_response(request_id, &serialized)
+    }
+
+    fn initialization_error(&self, error: &ErrorRequest) -> Result<R, Error> {
         let url = format!(
-            "http://{}/{}/runtime/invocation/{}/response",
+            "http://{}/{}/runtime/init/error",
+            self.api_base, self.version
+        );
+        let resp = post_error(&self.transport, &url, error)?;
+
+        handle_response!(resp);
+
+        Ok(resp)
+    }
+
+    fn invocation_error(&self, request_id: &str, error: &ErrorRequest) -> Result<R, Error> {
+        let url = format!(
+            "http://{}/{}/runtime/invocation/{}/error",
             self.api_base, self.version, request_id
         );
-        // TODO - Utilize a user-defined JSON serializer?
-        let serialized = match serde_json::to_string(response) {
-            Ok(ser) => ser,
-            Err(err) => {
-                return Err(Error::new(format!(
-                    "Failed serializing output to JSON. {}",
-                    err
-                )))
-            }
-        };
-        let resp = self.transport.post(&url, Some(&serialized), None)?;
+        let resp = post_error(&self.transport, &url, error)?;
 
         handle_response!(resp);
 
         Ok(resp)
     }
 
-    fn initialization_error(
-        &self,
-        error_type: Option<&str>,
-        error_req: Option<&str>,
-    ) -> Result<R, Error> {
+    fn restore_next(&mut self) -> Result<R, Error> {
         let url = format!(
-            "http://{}/{}/runtime/init/error",
+            "http://{}/{}/runtime/restore/next",
             self.api_base, self.version
         );
-        let headers = error_type.map(|et| (vec![AWS_FUNC_ERR_TYPE], vec![et]));
-
-        let resp = self.transport.post(&url, error_req, headers)?;
+        let resp = self.transport.get(&url, None, None)?;
 
         handle_response!(resp);
 
         Ok(resp)
     }
 
-    fn invocation_error(
-        &self,
-        request_id: &str,
-        error_type: Option<&str>,
-        error_req: Option<&str>,
-    ) -> Result<R, Error> {
+    fn restore_error(&self, error: &ErrorRequest) -> Result<R, Error> {
         let url = format!(
-            "http://{}/{}/runtime/invocation/{}/error",
-            self.api_base, self.version, request_id
+            "http://{}/{}/runtime/restore/error",
+            self.api_base, self.version
         );
-        let headers = error_type.map(|et| (vec![AWS_FUNC_ERR_TYPE], vec![et]));
-
-        let resp = self.transport.post(&url, error_req, headers)?;
+        let resp = post_error(&self.transport, &url, error)?;
 
         handle_response!(resp);
 
         Ok(resp)
     }
 }
+
+/// Posts an error to one of the error endpoints of the runtime API,
+/// along with the error type and X-Ray error cause headers.
+///
+/// A header whose value can't be sent - e.g. an error type containing non-ASCII characters, or an oversized error cause -
+/// is left out rather than failing the request, since the error is reported in the body as well.
+pub(crate) fn post_error<R, T>(transport: &T, url: &str, error: &ErrorRequest) -> Result<R, Error>
+where
+    R: LambdaAPIResponse,
+    T: Transport<R>,
+{
+    let body = error.to_json();
+    let cause = error.xray_error_cause();
+    let mut headers = (Vec::new(), Vec::new());
+    if is_header_value(&error.error_type) {
+        headers.0.push(AWS_FUNC_ERR_TYPE);
+        headers.1.push(error.error_type.as_str());
+    }
+    if cause.len() <= MAX_XRAY_ERROR_CAUSE_SIZE && is_header_value(&cause) {
+        headers.0.push(AWS_FUNC_XRAY_ERR_CAUSE);
+        headers.1.push(cause.as_str());
+    }
+    transport.post(url, Some(&body), Some(headers))
+}
+
+/// Returns whether `value` only contains the characters allowed in an HTTP header value - visible ASCII, spaces and tabs.
+fn is_header_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| matches!(b, b' ' | b'\t' | 0x21..=0x7E))
+}
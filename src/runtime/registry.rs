@@ -0,0 +1,268 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use super::init::InitContext;
+use super::{EventHandler, Initializer};
+use crate::data::context::{ContextKind, RefContextKind};
+use crate::data::env::RuntimeEnvVars;
+use crate::data::response::LambdaAPIResponse;
+use crate::error::ErrorRequest;
+
+use std::fmt::Display;
+
+use serde::Serialize;
+use serde_json::value::RawValue;
+
+/// The error type reported when no initializer is registered under the name set in the `_HANDLER` env-var.
+pub const HANDLER_NOT_FOUND_ERR_TYPE: &str = "Runtime.HandlerNotFound";
+
+/// The output of an event handler registered in a [`HandlerRegistry`], already serialized to JSON.
+pub type HandlerOutput = Box<RawValue>;
+
+/// The event handler of a [`HandlerRegistry`], wrapping the handler returned by the selected initializer.
+///
+/// The output of the wrapped handler is serialized to a [`HandlerOutput`], and its errors are converted to an [`ErrorRequest`]
+/// with the name of the handler's error type as the error type.
+pub type RegisteredHandler<ENV, R, CTX = RefContextKind> =
+    EventHandler<ENV, R, HandlerOutput, ErrorRequest, CTX>;
+
+/// A boxed initializer, returning a [`RegisteredHandler`] or the error to report to the Lambda service.
+type RegisteredInitializer<ENV, R, CTX> =
+    Box<dyn Fn(&mut InitContext<ENV>) -> Result<RegisteredHandler<ENV, R, CTX>, ErrorRequest>>;
+
+/// A registry of named initializers, for shipping several functions in a single binary.
+///
+/// A runtime created using [`crate::runtime::DefaultRuntime::from_registry`] or
+/// [`crate::runtime::DefaultRuntime::from_registry_with_env_vars`] runs the initializer registered under the name
+/// set in the `_HANDLER` env-var - the handler configured for the function - and reports a [`HANDLER_NOT_FOUND_ERR_TYPE`]
+/// init error if there is none.
+///
+/// Each initializer may return an event handler with its own output and error types.
+pub struct HandlerRegistry<ENV, R, CTX = RefContextKind>
+where
+    ENV: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+    CTX: ContextKind<ENV, R>,
+{
+    handlers: Vec<(String, RegisteredInitializer<ENV, R, CTX>)>,
+}
+
+impl<ENV, R, CTX> HandlerRegistry<ENV, R, CTX>
+where
+    ENV: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+    CTX: ContextKind<ENV, R>,
+{
+    pub fn new() -> Self {
+        Self {
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Returns the names of the registered handlers, in the order they were registered.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.handlers.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Runs the initializer registered under the name of the handler configured for the function.
+    pub(crate) fn initialize(
+        &self,
+        init_context: &mut InitContext<ENV>,
+    ) -> Result<RegisteredHandler<ENV, R, CTX>, ErrorRequest> {
+        let name = init_context.env_vars().get_handler().unwrap_or_default();
+        match self
+            .handlers
+            .iter()
+            .find(|(registered, _)| registered == name)
+        {
+            Some((_, initializer)) => initializer(init_context),
+            None => Err(ErrorRequest::new(
+                HANDLER_NOT_FOUND_ERR_TYPE,
+                &format!(
+                    "No handler is registered as `{}`. Registered handlers: {}",
+                    name,
+                    self.names().collect::<Vec<_>>().join(", ")
+                ),
+            )),
+        }
+    }
+}
+
+impl<ENV, R, CTX> HandlerRegistry<ENV, R, CTX>
+where
+    ENV: RuntimeEnvVars + 'static,
+    R: LambdaAPIResponse + 'static,
+    CTX: ContextKind<ENV, R> + 'static,
+{
+    /// Registers `initializer` under `name`, replacing any initializer previously registered under that name.
+    pub fn with_handler<OUT, ERR>(
+        mut self,
+        name: &str,
+        initializer: Initializer<ENV, R, OUT, ERR, CTX>,
+    ) -> Self
+    where
+        OUT: Serialize + 'static,
+        ERR: Display + 'static,
+    {
+        let registered: RegisteredInitializer<ENV, R, CTX> = Box::new(move |init_context| {
+            initializer(init_context)
+                .map(register_handler)
+                .map_err(|init_err| {
+                    init_context.error.take().unwrap_or_else(|| {
+                        ErrorRequest::new("Runtime.InitError", &init_err.to_string())
+                    })
+                })
+        });
+        self.handlers.retain(|(registered, _)| registered != name);
+        self.handlers.push((name.to_string(), registered));
+        self
+    }
+}
+
+impl<ENV, R, CTX> Default for HandlerRegistry<ENV, R, CTX>
+where
+    ENV: RuntimeEnvVars,
+    R: LambdaAPIResponse,
+    CTX: ContextKind<ENV, R>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wraps an event handler in a [`RegisteredHandler`].
+fn register_handler<ENV, R, OUT, ERR, CTX>(
+    handler: EventHandler<ENV, R, OUT, ERR, CTX>,
+) -> RegisteredHandler<ENV, R, CTX>
+where
+    ENV: RuntimeEnvVars + 'static,
+    R: LambdaAPIResponse + 'static,
+    OUT: Serialize + 'static,
+    ERR: Display + 'static,
+    CTX: ContextKind<ENV, R> + 'static,
+{
+    Box::new(move |event, context| {
+        let output = handler(event, context).map_err(|err| ErrorRequest::from_error(&err))?;
+        serde_json::value::to_raw_value(&output).map_err(|err| {
+            ErrorRequest::from_error(&crate::error::Error::new(format!(
+                "Failed serializing output to JSON. {}",
+                err
+            )))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::context::RefLambdaContext;
+    use crate::data::env::LambdaRuntimeEnv;
+    use crate::data::response::TestResponse;
+
+    use std::time::Instant;
+
+    type Registry = HandlerRegistry<LambdaRuntimeEnv, TestResponse>;
+
+    fn orders(
+        _: &mut InitContext<LambdaRuntimeEnv>,
+    ) -> Result<EventHandler<LambdaRuntimeEnv, TestResponse, u64, String>, String> {
+        Ok(Box::new(|event, _| {
+            event
+                .and_then(|event| event.parse().ok())
+                .ok_or_else(|| "Not an order id".to_string())
+        }))
+    }
+
+    fn payments(
+        _: &mut InitContext<LambdaRuntimeEnv>,
+    ) -> Result<EventHandler<LambdaRuntimeEnv, TestResponse, String, String>, String> {
+        Err("Missing payment provider".to_string())
+    }
+
+    fn registry() -> Registry {
+        Registry::new()
+            .with_handler("orders", orders)
+            .with_handler("payments", payments)
+    }
+
+    fn initialize(
+        registry: &Registry,
+        handler: &str,
+    ) -> Result<RegisteredHandler<LambdaRuntimeEnv, TestResponse>, ErrorRequest> {
+        let env_vars = LambdaRuntimeEnv::builder().with_handler(handler).build();
+        registry.initialize(&mut InitContext::new(&env_vars, Instant::now()))
+    }
+
+    #[test]
+    fn runs_the_handler_selected_by_the_handler_env_var() {
+        let handler = initialize(&registry(), "orders").unwrap();
+        let env_vars = LambdaRuntimeEnv::builder().build();
+        let response = TestResponse::default();
+
+        let output = handler(Some("42"), RefLambdaContext::new(&env_vars, &response)).unwrap();
+        assert_eq!(output.get(), "42");
+        let error = handler(Some("x"), RefLambdaContext::new(&env_vars, &response)).unwrap_err();
+        assert_eq!(error.error_type, "String");
+        assert_eq!(error.error_message, "Not an order id");
+    }
+
+    #[test]
+    fn reports_initializer_errors() {
+        let error = initialize(&registry(), "payments").err().unwrap();
+        assert_eq!(error.error_type, "Runtime.InitError");
+        assert_eq!(error.error_message, "Missing payment provider");
+        assert!(error.stack_trace.is_empty());
+    }
+
+    #[test]
+    fn reports_missing_handlers() {
+        let error = initialize(&registry(), "refunds").err().unwrap();
+        assert_eq!(error.error_type, HANDLER_NOT_FOUND_ERR_TYPE);
+        assert_eq!(
+            error.error_message,
+            "No handler is registered as `refunds`. Registered handlers: orders, payments"
+        );
+    }
+
+    #[test]
+    fn replaces_handlers_registered_under_the_same_name() {
+        let registry = registry().with_handler("orders", payments);
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["payments", "orders"]);
+        let error = initialize(&registry, "orders").err().unwrap();
+        assert_eq!(error.error_message, "Missing payment provider");
+    }
+
+    #[cfg(feature = "ureq")]
+    #[test]
+    fn creates_runtimes_from_a_registry() {
+        use crate::backends::ureq::{UreqResponse, UreqTransport};
+        use crate::runtime::DefaultRuntime;
+
+        type Runtime = DefaultRuntime<
+            UreqResponse,
+            UreqTransport,
+            LambdaRuntimeEnv,
+            HandlerOutput,
+            ErrorRequest,
+        >;
+
+        fn orders(
+            _: &mut InitContext<LambdaRuntimeEnv>,
+        ) -> Result<EventHandler<LambdaRuntimeEnv, UreqResponse, u64, String>, String> {
+            Ok(Box::new(|_, _| Ok(42)))
+        }
+
+        fn runtime(handler: &str) -> Runtime {
+            Runtime::from_registry_with_env_vars(
+                "2018-06-01",
+                LambdaRuntimeEnv::builder().with_handler(handler).build(),
+                HandlerRegistry::<_, UreqResponse>::new().with_handler("orders", orders),
+            )
+        }
+
+        assert!(runtime("orders").initialize(None).is_ok());
+        let error = runtime("refunds").initialize(None).err().unwrap();
+        assert_eq!(error.error_type, HANDLER_NOT_FOUND_ERR_TYPE);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use super::lock;
+
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The time spent by the runtime in each phase of an invocation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// The time blocked waiting for the event in [`crate::runtime::LambdaRuntime::next_invocation`].
+    pub next_wait: Duration,
+    /// The time spent deserializing the event by [`crate::runtime::entry::deserialize_event`] - used by the handlers
+    /// generated by `#[rtlambda::main]` and the tower adapter - the event router and the HTTP adapter.
+    pub deserialize: Duration,
+    /// The time spent running the event handler, excluding [`Timings::deserialize`].
+    /// Handlers that deserialize the event on their own include it in this phase.
+    pub handler: Duration,
+    /// The time spent serializing the handler's output.
+    pub serialize: Duration,
+    /// The time spent sending the handler's output or error to the Lambda service.
+    pub acknowledge: Duration,
+}
+
+impl Timings {
+    /// Returns the time spent in all phases of the invocation.
+    pub fn total(&self) -> Duration {
+        self.next_wait + self.deserialize + self.handler + self.serialize + self.acknowledge
+    }
+
+    fn add(&mut self, other: &Timings) {
+        self.next_wait += other.next_wait;
+        self.deserialize += other.deserialize;
+        self.handler += other.handler;
+        self.serialize += other.serialize;
+        self.acknowledge += other.acknowledge;
+    }
+}
+
+thread_local! {
+    /// The time spent deserializing events on this thread since it was last taken by [`take_deserialize`].
+    static DESERIALIZE: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Runs `f`, which deserializes the event of the current invocation, adding the time it took to [`Timings::deserialize`].
+///
+/// The handler runs on the thread of the runtime's loop, which collects the time using [`take_deserialize`].
+pub(crate) fn time_deserialize<T>(f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    DESERIALIZE.with(|total| total.set(total.get() + start.elapsed()));
+    result
+}
+
+/// Returns the time spent deserializing events since the last call, resetting it.
+pub(crate) fn take_deserialize() -> Duration {
+    DESERIALIZE.with(Cell::take)
+}
+
+/// The statistics of a single invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvocationStats {
+    /// The index of the invocation in the life of the runtime, starting from 0.
+    pub index: u64,
+    /// `true` for the first invocation after initialization.
+    pub cold_start: bool,
+    pub timings: Timings,
+}
+
+/// Statistics collected by [`crate::runtime::DefaultRuntime`] during its life.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeStats {
+    /// The time spent running the initializer, set once initialization completes.
+    pub init_duration: Option<Duration>,
+    /// The number of invocations processed so far.
+    pub invocations: u64,
+    /// The statistics of the last invocation.
+    pub last: Option<InvocationStats>,
+    /// The sum of the timings of all invocations.
+    pub total: Timings,
+}
+
+impl RuntimeStats {
+    /// Returns the average timings of all invocations, or `None` if there were no invocations.
+    pub fn average(&self) -> Option<Timings> {
+        let count = u32::try_from(self.invocations).ok().filter(|c| *c > 0)?;
+        Some(Timings {
+            next_wait: self.total.next_wait / count,
+            deserialize: self.total.deserialize / count,
+            handler: self.total.handler / count,
+            serialize: self.total.serialize / count,
+            acknowledge: self.total.acknowledge / count,
+        })
+    }
+}
+
+/// A shared handle to the [`RuntimeStats`] of a runtime, updated by the runtime after each invocation.
+///
+/// Returned by [`crate::runtime::DefaultRuntime::get_stats`], and can be cloned and sent to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct StatsHandle {
+    stats: Arc<Mutex<RuntimeStats>>,
+}
+
+impl StatsHandle {
+    /// Returns a copy of the current statistics.
+    pub fn snapshot(&self) -> RuntimeStats {
+        lock(&self.stats).clone()
+    }
+
+    pub(crate) fn set_init_duration(&self, duration: Duration) {
+        lock(&self.stats).init_duration = Some(duration);
+    }
+
+    /// Records the timings of an invocation, returning its statistics.
+    pub(crate) fn record(&self, timings: Timings) -> InvocationStats {
+        let mut stats = lock(&self.stats);
+        let invocation = InvocationStats {
+            index: stats.invocations,
+            cold_start: stats.invocations == 0,
+            timings,
+        };
+        stats.invocations += 1;
+        stats.total.add(&timings);
+        stats.last = Some(invocation);
+        invocation
+    }
+}
+
+/// Writes the statistics of an invocation as metrics in the CloudWatch Embedded Metric Format, using a copy of `template`.
+///
+/// The init duration is only written for cold starts.
+#[cfg(feature = "metrics")]
+pub(crate) fn write_metrics(
+    template: &crate::metrics::MetricsLogger,
+    invocation: &InvocationStats,
+    init_duration: Option<Duration>,
+) -> Result<(), crate::error::Error> {
+    use crate::metrics::Unit;
+
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let timings = &invocation.timings;
+    let mut metrics = template.clone();
+    metrics.put_metric(
+        "ColdStart",
+        u8::from(invocation.cold_start).into(),
+        Unit::Count,
+    )?;
+    if let (true, Some(init_duration)) = (invocation.cold_start, init_duration) {
+        metrics.put_metric("InitDuration", ms(init_duration), Unit::Milliseconds)?;
+    }
+    metrics.put_metric("NextWait", ms(timings.next_wait), Unit::Milliseconds)?;
+    metrics.put_metric("Deserialize", ms(timings.deserialize), Unit::Milliseconds)?;
+    metrics.put_metric("Handler", ms(timings.handler), Unit::Milliseconds)?;
+    metrics.put_metric("Serialize", ms(timings.serialize), Unit::Milliseconds)?;
+    metrics.put_metric("Acknowledge", ms(timings.acknowledge), Unit::Milliseconds)?;
+    metrics.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::entry::deserialize_event;
+
+    #[test]
+    fn records_the_time_spent_deserializing() {
+        take_deserialize();
+        let event: Vec<u64> = deserialize_event(Some("[1, 2, 3]")).unwrap();
+        assert_eq!(event, vec![1, 2, 3]);
+        time_deserialize(|| std::thread::sleep(Duration::from_millis(5)));
+        assert!(take_deserialize() >= Duration::from_millis(5));
+        assert_eq!(take_deserialize(), Duration::ZERO);
+    }
+
+    #[test]
+    fn sums_and_averages_the_timings() {
+        let stats = StatsHandle::default();
+        let ms = Duration::from_millis;
+        let first = stats.record(Timings {
+            next_wait: ms(10),
+            deserialize: ms(2),
+            handler: ms(20),
+            serialize: ms(1),
+            acknowledge: ms(3),
+        });
+        let second = stats.record(Timings {
+            deserialize: ms(4),
+            handler: ms(40),
+            ..Timings::default()
+        });
+        assert!(first.cold_start);
+        assert!(!second.cold_start);
+        assert_eq!(second.index, 1);
+        assert_eq!(first.timings.total(), ms(36));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.invocations, 2);
+        assert_eq!(snapshot.last, Some(second));
+        assert_eq!(snapshot.total.deserialize, ms(6));
+        let average = snapshot.average().unwrap();
+        assert_eq!(average.deserialize, ms(3));
+        assert_eq!(average.handler, ms(30));
+        assert_eq!(RuntimeStats::default().average(), None);
+    }
+}
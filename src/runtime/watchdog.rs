@@ -0,0 +1,291 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use super::lock;
+use crate::data::response::LambdaAPIResponse;
+use crate::error::{ErrorRequest, StackFrame};
+use crate::transport::Transport;
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The error type reported by the watchdog when an invocation is about to time out.
+pub static TIMEOUT_ERR_TYPE: &str = "Sandbox.Timedout";
+
+/// The last checkpoint label set by the event handler, along with the time it was set.
+static CHECKPOINT: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+
+/// Records a checkpoint `label` for the current invocation.
+///
+/// The last checkpoint is included in the diagnostics reported by the [`Watchdog`] when an invocation times out,
+/// helping to pinpoint where the handler was stuck. Calling this function without an enabled watchdog has no effect besides storing the label.
+pub fn checkpoint<S: Into<String>>(label: S) {
+    *lock(&CHECKPOINT) = Some((label.into(), Instant::now()));
+}
+
+/// Configures the behaviour of a [`Watchdog`].
+#[derive(Debug, Clone, Copy)]
+pub struct WatchdogConfig {
+    /// How long before the invocation deadline the timeout is reported.
+    pub margin: Duration,
+    /// If set, the process exits with this code after reporting the timeout.
+    pub exit_code: Option<i32>,
+}
+
+impl WatchdogConfig {
+    /// Sets how long before the invocation deadline the timeout is reported.
+    pub fn with_margin(mut self, margin: Duration) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    /// Sets the code the process exits with after reporting a timeout.
+    pub fn with_exit_code(mut self, exit_code: Option<i32>) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            margin: Duration::from_millis(200),
+            exit_code: None,
+        }
+    }
+}
+
+/// The invocation currently watched by the [`Watchdog`].
+struct Invocation {
+    request_id: String,
+    /// The deadline as a duration since the unix epoch.
+    deadline: Option<Duration>,
+    /// The phases the runtime went through during the invocation, starting with the time the event was received.
+    phases: Vec<(&'static str, Instant)>,
+    reported: bool,
+}
+
+struct Shared {
+    invocation: Mutex<Option<Invocation>>,
+    changed: Condvar,
+}
+
+/// A background thread that watches the deadline of the current invocation and reports a
+/// [`TIMEOUT_ERR_TYPE`] invocation error shortly before the Lambda service stops the function.
+///
+/// The report includes the time elapsed in each phase of the invocation and the last label set using [`checkpoint`].
+/// Created by [`crate::runtime::DefaultRuntime::enable_watchdog`].
+pub struct Watchdog {
+    shared: Arc<Shared>,
+}
+
+impl Watchdog {
+    /// Spawns the watchdog thread, reporting errors to `api_url` (host and version) using a new instance of the transport `T`.
+    pub(crate) fn spawn<R, T>(config: WatchdogConfig, api_url: String) -> Self
+    where
+        R: LambdaAPIResponse + 'static,
+        T: Transport<R> + 'static,
+    {
+        let shared = Arc::new(Shared {
+            invocation: Mutex::new(None),
+            changed: Condvar::new(),
+        });
+        let watched = shared.clone();
+        thread::Builder::new()
+            .name("rtlambda-watchdog".to_string())
+            .spawn(move || watch::<R, T>(config, api_url, &watched))
+            .expect("Failed spawning the watchdog thread");
+
+        Self { shared }
+    }
+
+    /// Starts watching a new invocation.
+    pub(crate) fn begin(&self, request_id: &str, deadline: Option<Duration>) {
+        *lock(&CHECKPOINT) = None;
+        *lock(&self.shared.invocation) = Some(Invocation {
+            request_id: request_id.to_string(),
+            deadline,
+            phases: vec![("received", Instant::now())],
+            reported: false,
+        });
+        self.shared.changed.notify_one();
+    }
+
+    /// Marks the start of a new phase of the current invocation.
+    pub(crate) fn phase(&self, name: &'static str) {
+        if let Some(invocation) = lock(&self.shared.invocation).as_mut() {
+            invocation.phases.push((name, Instant::now()));
+        }
+    }
+
+    /// Returns `true` if a timeout was already reported for the current invocation.
+    pub(crate) fn timed_out(&self) -> bool {
+        lock(&self.shared.invocation)
+            .as_ref()
+            .map(|inv| inv.reported)
+            .unwrap_or(false)
+    }
+
+    /// Stops watching the current invocation.
+    pub(crate) fn end(&self) {
+        lock(&self.shared.invocation).take();
+        self.shared.changed.notify_one();
+    }
+}
+
+/// The watchdog thread's loop.
+fn watch<R, T>(config: WatchdogConfig, api_url: String, shared: &Shared)
+where
+    R: LambdaAPIResponse,
+    T: Transport<R>,
+{
+    let transport = T::default();
+    let mut guard = lock(&shared.invocation);
+    loop {
+        let fire_at = match guard.as_ref() {
+            Some(Invocation {
+                deadline: Some(deadline),
+                reported: false,
+                ..
+            }) => deadline.saturating_sub(config.margin),
+            _ => {
+                guard = shared
+                    .changed
+                    .wait(guard)
+                    .unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        if now < fire_at {
+            // Wake up on the deadline or when the invocation changes, and re-evaluate.
+            guard = shared
+                .changed
+                .wait_timeout(guard, fire_at - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+            continue;
+        }
+
+        let invocation = guard.as_mut().expect("checked above");
+        invocation.reported = true;
+        let url = format!(
+            "{}/runtime/invocation/{}/error",
+            api_url, invocation.request_id
+        );
+        let report = error_request(invocation);
+        drop(guard);
+
+        // Also log the report, so that it shows up even if the process is stopped before the request completes.
+        eprintln!("{}", report.to_json());
+        if let Err(err) = super::post_error(&transport, &url, &report) {
+            eprintln!("Failed reporting invocation timeout. {}", err);
+        }
+        if let Some(code) = config.exit_code {
+            std::process::exit(code);
+        }
+
+        guard = lock(&shared.invocation);
+    }
+}
+
+/// Formats the diagnostics of a timed out invocation as an [`ErrorRequest`].
+fn error_request(invocation: &Invocation) -> ErrorRequest {
+    let now = Instant::now();
+    let received = invocation.phases[0].1;
+    let mut trace: Vec<StackFrame> = invocation
+        .phases
+        .iter()
+        .enumerate()
+        .map(|(i, (name, start))| {
+            let end = invocation.phases.get(i + 1).map(|p| p.1).unwrap_or(now);
+            frame(format!(
+                "phase {}: started at +{}ms, lasted {}ms",
+                name,
+                start.duration_since(received).as_millis(),
+                end.duration_since(*start).as_millis()
+            ))
+        })
+        .collect();
+    let message = match lock(&CHECKPOINT).as_ref() {
+        Some((label, at)) => {
+            trace.push(frame(format!(
+                "checkpoint {}: set at +{}ms",
+                label,
+                at.saturating_duration_since(received).as_millis()
+            )));
+            format!(
+                "Invocation {} is about to time out after {}ms. Last checkpoint: {}",
+                invocation.request_id,
+                now.duration_since(received).as_millis(),
+                label
+            )
+        }
+        None => format!(
+            "Invocation {} is about to time out after {}ms. No checkpoint was set",
+            invocation.request_id,
+            now.duration_since(received).as_millis()
+        ),
+    };
+
+    ErrorRequest {
+        stack_trace: trace,
+        ..ErrorRequest::new(TIMEOUT_ERR_TYPE, &message)
+    }
+}
+
+fn frame(label: String) -> StackFrame {
+    StackFrame {
+        label,
+        ..StackFrame::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_phases_and_the_last_checkpoint() {
+        let received = Instant::now();
+        let invocation = Invocation {
+            request_id: "8476a536".to_string(),
+            deadline: None,
+            phases: vec![
+                ("received", received),
+                ("handler", received + Duration::from_millis(10)),
+            ],
+            reported: false,
+        };
+
+        *lock(&CHECKPOINT) = None;
+        let report = error_request(&invocation);
+        assert_eq!(report.error_type, TIMEOUT_ERR_TYPE);
+        assert!(report
+            .error_message
+            .starts_with("Invocation 8476a536 is about to time out after "));
+        assert!(report.error_message.ends_with(". No checkpoint was set"));
+        assert_eq!(report.stack_trace.len(), 2);
+        assert_eq!(
+            report.stack_trace[0].label,
+            "phase received: started at +0ms, lasted 10ms"
+        );
+        assert!(report.stack_trace[1]
+            .label
+            .starts_with("phase handler: started at +10ms, lasted "));
+
+        checkpoint("querying the database");
+        let report = error_request(&invocation);
+        assert!(report
+            .error_message
+            .ends_with("ms. Last checkpoint: querying the database"));
+        assert_eq!(report.stack_trace.len(), 3);
+        assert!(report.stack_trace[2]
+            .label
+            .starts_with("checkpoint querying the database: set at +"));
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::data::context::OwnedLambdaContext;
+use crate::error::Error;
+use crate::runtime::entry::{block_on, deserialize_event};
+use crate::runtime::OwnedEventHandler;
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::future::{poll_fn, ready, Ready};
+use std::marker::PhantomData;
+use std::task::{Context, Poll};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+pub use tower_service::Service;
+
+/// The request type accepted by services run through [`service_handler`],
+/// bundling the deserialized event payload with an owned context object.
+#[derive(Debug, Clone)]
+pub struct LambdaEvent<IN> {
+    /// The event sent by AWS Lambda, deserialized to the user-defined type `IN`.
+    pub payload: IN,
+    /// The context object of the invocation.
+    pub context: OwnedLambdaContext,
+}
+
+impl<IN> LambdaEvent<IN> {
+    pub fn new(payload: IN, context: OwnedLambdaContext) -> Self {
+        Self { payload, context }
+    }
+}
+
+/// Wraps a [`Service`] accepting [`LambdaEvent`] requests in an [`crate::runtime::OwnedEventHandler`] that can be returned
+/// from the initializer of a [`crate::runtime::DefaultRuntime`].
+///
+/// The handler deserializes the event JSON into `IN` using [`crate::runtime::entry::deserialize_event`] - an empty event is deserialized from `null` -
+/// waits for the service to become ready and blocks the runtime thread until the future returned by [`Service::call`] resolves,
+/// using [`crate::runtime::entry::block_on`]. Errors during deserialization are converted into the service's error type.
+///
+/// Layers that depend on the `tokio` reactor or timers, such as `tower::timeout::Timeout`, require the `tokio` feature,
+/// which drives the service on a current-thread `tokio` runtime. Without it only executor-agnostic layers may be used.
+///
+/// The handler accepts an [`OwnedLambdaContext`], therefore the runtime should be created with [`crate::data::context::OwnedContextKind`].
+pub fn service_handler<S, IN>(service: S) -> OwnedEventHandler<S::Response, S::Error>
+where
+    S: Service<LambdaEvent<IN>> + 'static,
+    S::Response: Serialize,
+    S::Error: From<Error> + Display,
+    IN: DeserializeOwned,
+{
+    let service = RefCell::new(service);
+
+    Box::new(move |event, context| {
+        let payload = deserialize_event::<IN>(event)?;
+        let request = LambdaEvent::new(payload, context);
+
+        let mut service = service.borrow_mut();
+        block_on(async {
+            poll_fn(|cx| service.poll_ready(cx)).await?;
+            service.call(request).await
+        })
+    })
+}
+
+/// A [`Service`] that calls a synchronous handler function with the payload and context of each [`LambdaEvent`].
+///
+/// Allows applying existing tower middleware to `rtlambda` handlers, see [`handler_service`].
+pub struct HandlerService<F, IN> {
+    handler: F,
+    _payload: PhantomData<fn(IN)>,
+}
+
+/// Creates a [`HandlerService`] from a handler function accepting the event payload and an [`OwnedLambdaContext`].
+pub fn handler_service<F, IN, OUT, ERR>(handler: F) -> HandlerService<F, IN>
+where
+    F: Fn(IN, OwnedLambdaContext) -> Result<OUT, ERR>,
+{
+    HandlerService {
+        handler,
+        _payload: PhantomData,
+    }
+}
+
+impl<F: Clone, IN> Clone for HandlerService<F, IN> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            _payload: PhantomData,
+        }
+    }
+}
+
+impl<F, IN, OUT, ERR> Service<LambdaEvent<IN>> for HandlerService<F, IN>
+where
+    F: Fn(IN, OwnedLambdaContext) -> Result<OUT, ERR>,
+{
+    type Response = OUT;
+    type Error = ERR;
+    type Future = Ready<Result<OUT, ERR>>;
+
+    #[inline(always)]
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), ERR>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn call(&mut self, request: LambdaEvent<IN>) -> Self::Future {
+        ready((self.handler)(request.payload, request.context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::context::{ContextEnv, LambdaContext};
+    use crate::data::response::TestResponse;
+    use std::sync::Arc;
+
+    #[test]
+    fn runs_services_as_event_handlers() {
+        let handler = service_handler(handler_service(
+            |payload: Option<u32>, context: OwnedLambdaContext| {
+                Ok::<_, Error>(format!("{:?} {:?}", payload, context.aws_request_id()))
+            },
+        ));
+        let response = TestResponse {
+            aws_request_id: Some("8476a536".to_string()),
+            ..Default::default()
+        };
+        let context = || OwnedLambdaContext::new(Arc::new(ContextEnv::default()), &response);
+
+        assert_eq!(
+            handler(Some("1"), context()).unwrap(),
+            "Some(1) Some(\"8476a536\")"
+        );
+        // A missing event is deserialized from null.
+        assert_eq!(handler(None, context()).unwrap(), "None Some(\"8476a536\")");
+        assert!(handler(Some("\"1\""), context())
+            .unwrap_err()
+            .to_string()
+            .starts_with("Failed deserializing event from JSON."));
+    }
+}
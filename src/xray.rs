@@ -0,0 +1,374 @@
+// Copyright 2022 Guy Or and the "rtlambda" authors. All rights reserved.
+
+// `SPDX-License-Identifier: MIT OR Apache-2.0`
+
+use crate::data::env::RuntimeEnvVars;
+use crate::data::trace::TraceHeader;
+use crate::error::Error;
+use crate::runtime::lock;
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+/// The env-var holding the address of the X-Ray daemon, set by AWS Lambda when active tracing is enabled.
+pub static XRAY_DAEMON_ADDRESS: &str = "AWS_XRAY_DAEMON_ADDRESS";
+
+/// The header that prefixes every document sent to the X-Ray daemon.
+static DAEMON_HEADER: &str = "{\"format\":\"json\",\"version\":1}\n";
+
+/// The name of the subsegment recorded by the runtime for each invocation.
+pub static INVOCATION_SUBSEGMENT: &str = "Invocation";
+
+/// Sends segment documents to the X-Ray daemon over UDP.
+#[derive(Debug)]
+pub struct Emitter {
+    socket: UdpSocket,
+    address: SocketAddr,
+}
+
+impl Emitter {
+    /// Creates an emitter sending to `address`, which is either `host:port` or in the
+    /// `tcp:host:port udp:host:port` form used by the `AWS_XRAY_DAEMON_ADDRESS` env-var.
+    pub fn new(address: &str) -> Result<Self, Error> {
+        let udp = address
+            .split_whitespace()
+            .find_map(|part| part.strip_prefix("udp:"))
+            .unwrap_or_else(|| address.trim());
+        let address = udp
+            .to_socket_addrs()
+            .map_err(|err| Error::new(format!("Invalid X-Ray daemon address `{}`. {}", udp, err)))?
+            .next()
+            .ok_or_else(|| Error::new(format!("Invalid X-Ray daemon address `{}`", udp)))?;
+        let bind = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(bind)
+            .map_err(|err| Error::new(format!("Failed binding X-Ray UDP socket. {}", err)))?;
+
+        Ok(Self { socket, address })
+    }
+
+    /// Creates an emitter sending to the address set in the `AWS_XRAY_DAEMON_ADDRESS` env-var, read from `env_vars`.
+    pub fn from_env_vars<E: RuntimeEnvVars>(env_vars: &E) -> Result<Self, Error> {
+        match env_vars.get_other(XRAY_DAEMON_ADDRESS) {
+            Some(address) => Self::new(address),
+            None => Err(Error::new(format!("{} is not set", XRAY_DAEMON_ADDRESS))),
+        }
+    }
+
+    /// Sends a single segment document to the daemon.
+    pub fn send(&self, document: &Value) -> Result<(), Error> {
+        let packet = format!("{}{}", DAEMON_HEADER, document);
+        self.socket
+            .send_to(packet.as_bytes(), self.address)
+            .map(|_| ())
+            .map_err(|err| Error::new(format!("Failed sending segment to X-Ray daemon. {}", err)))
+    }
+}
+
+/// The subsegment of the invocation currently processed by the runtime, used as the parent of [`begin_subsegment`].
+struct Current {
+    trace: TraceHeader,
+    emitter: Option<Arc<Emitter>>,
+}
+
+static CURRENT: Mutex<Option<Current>> = Mutex::new(None);
+
+/// A subsegment recording the timing of a unit of work - usually a downstream call - within a trace.
+///
+/// The subsegment is sent to the X-Ray daemon when it is ended, either explicitly using [`Subsegment::end`] or when dropped.
+/// Subsegments of traces that are not sampled are never sent, but can still be used to propagate the trace header.
+#[derive(Debug)]
+pub struct Subsegment {
+    name: String,
+    id: String,
+    trace: TraceHeader,
+    start_time: f64,
+    namespace: Option<String>,
+    error: bool,
+    fault: bool,
+    annotations: Map<String, Value>,
+    metadata: Map<String, Value>,
+    emitter: Option<Arc<Emitter>>,
+    ended: bool,
+}
+
+impl Subsegment {
+    /// Starts a new subsegment named `name`, whose parent is the segment referenced by `trace`.
+    /// It is only sent when the trace is sampled and an `emitter` is given.
+    pub fn new(name: &str, trace: &TraceHeader, emitter: Option<Arc<Emitter>>) -> Self {
+        Self {
+            name: name.to_string(),
+            id: TraceHeader::new_segment_id(),
+            trace: trace.clone(),
+            start_time: now(),
+            namespace: None,
+            error: false,
+            fault: false,
+            annotations: Map::new(),
+            metadata: Map::new(),
+            emitter: emitter.filter(|_| trace.is_sampled()),
+            ended: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    #[inline(always)]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the trace header to send with downstream calls made within this subsegment.
+    pub fn trace_header(&self) -> TraceHeader {
+        self.trace.child(&self.id)
+    }
+
+    /// Starts a nested subsegment.
+    pub fn begin_subsegment(&self, name: &str) -> Subsegment {
+        Subsegment::new(name, &self.trace_header(), self.emitter.clone())
+    }
+
+    /// Sets the namespace of the subsegment - `aws` for AWS SDK calls or `remote` for other downstream calls.
+    pub fn set_namespace(&mut self, namespace: &str) {
+        self.namespace = Some(namespace.to_string());
+    }
+
+    /// Marks the subsegment as failed due to a client error.
+    pub fn set_error(&mut self, error: bool) {
+        self.error = error;
+    }
+
+    /// Marks the subsegment as failed due to a server error.
+    pub fn set_fault(&mut self, fault: bool) {
+        self.fault = fault;
+    }
+
+    /// Adds an indexed annotation, `value` should be a string, number or boolean.
+    pub fn add_annotation<V: Into<Value>>(&mut self, key: &str, value: V) {
+        self.annotations.insert(key.to_string(), value.into());
+    }
+
+    /// Adds non-indexed metadata under the `default` namespace.
+    pub fn add_metadata<V: Into<Value>>(&mut self, key: &str, value: V) {
+        self.metadata.insert(key.to_string(), value.into());
+    }
+
+    /// Ends the subsegment and sends it to the X-Ray daemon if the trace is sampled.
+    pub fn end(mut self) -> Result<(), Error> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.ended {
+            return Ok(());
+        }
+        self.ended = true;
+        match &self.emitter {
+            Some(emitter) => emitter.send(&self.to_document(now())),
+            None => Ok(()),
+        }
+    }
+
+    /// Formats the subsegment as an independent subsegment document.
+    fn to_document(&self, end_time: f64) -> Value {
+        let mut document = json!({
+            "name": self.name,
+            "id": self.id,
+            "trace_id": self.trace.root,
+            "type": "subsegment",
+            "start_time": self.start_time,
+            "end_time": end_time,
+        });
+        let fields = document.as_object_mut().expect("created as an object");
+        if let Some(parent) = &self.trace.parent {
+            fields.insert("parent_id".to_string(), json!(parent));
+        }
+        if let Some(namespace) = &self.namespace {
+            fields.insert("namespace".to_string(), json!(namespace));
+        }
+        if self.error {
+            fields.insert("error".to_string(), json!(true));
+        }
+        if self.fault {
+            fields.insert("fault".to_string(), json!(true));
+        }
+        if !self.annotations.is_empty() {
+            fields.insert("annotations".to_string(), json!(self.annotations));
+        }
+        if !self.metadata.is_empty() {
+            fields.insert("metadata".to_string(), json!({ "default": self.metadata }));
+        }
+        document
+    }
+}
+
+impl Drop for Subsegment {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Starts a subsegment nested in the subsegment of the current invocation.
+///
+/// Returns `None` when called outside of an invocation, or when the runtime received no trace header.
+pub fn begin_subsegment(name: &str) -> Option<Subsegment> {
+    lock(&CURRENT)
+        .as_ref()
+        .map(|current| Subsegment::new(name, &current.trace, current.emitter.clone()))
+}
+
+/// Returns the trace header to send with downstream calls made within the current invocation.
+pub fn current_trace_header() -> Option<TraceHeader> {
+    lock(&CURRENT).as_ref().map(|current| current.trace.clone())
+}
+
+/// Records an [`INVOCATION_SUBSEGMENT`] subsegment for each invocation processed by [`crate::runtime::DefaultRuntime`].
+///
+/// Created by [`crate::runtime::DefaultRuntime::enable_xray`].
+pub struct Recorder {
+    emitter: Arc<Emitter>,
+    invocation: Option<Subsegment>,
+}
+
+impl Recorder {
+    pub fn new(emitter: Emitter) -> Self {
+        Self {
+            emitter: Arc::new(emitter),
+            invocation: None,
+        }
+    }
+
+    /// Starts the subsegment of a new invocation, given the value of the `Lambda-Runtime-Trace-Id` header.
+    /// Invalid or missing headers disable recording for the invocation.
+    pub(crate) fn begin_invocation(&mut self, trace_id: Option<&str>) {
+        let trace = match trace_id.map(TraceHeader::parse) {
+            Some(Ok(trace)) => trace,
+            _ => {
+                *lock(&CURRENT) = None;
+                return;
+            }
+        };
+        let subsegment = Subsegment::new(INVOCATION_SUBSEGMENT, &trace, Some(self.emitter.clone()));
+        *lock(&CURRENT) = Some(Current {
+            trace: subsegment.trace_header(),
+            emitter: subsegment.emitter.clone(),
+        });
+        self.invocation = Some(subsegment);
+    }
+
+    /// Ends the subsegment of the current invocation, marking it as faulty if the handler failed.
+    pub(crate) fn end_invocation(&mut self, fault: bool) {
+        *lock(&CURRENT) = None;
+        if let Some(mut subsegment) = self.invocation.take() {
+            subsegment.set_fault(fault);
+            if let Err(err) = subsegment.end() {
+                eprintln!("{}", err);
+            }
+        }
+    }
+}
+
+/// Returns the current time as fractional seconds since the unix epoch, as expected by X-Ray.
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::env::LambdaRuntimeEnv;
+    use std::time::Duration;
+
+    static NOT_SAMPLED_ROOT: &str = "1-5759e988-bd862e3fe1be46a994272793";
+    static SAMPLED_ROOT: &str = "1-581cf771-a006649127e371903a2de979";
+    static PARENT: &str = "53995c3f42cd8ad8";
+
+    /// Receives a packet sent to the daemon stand-in, checks its header and returns the segment document.
+    fn receive(daemon: &UdpSocket) -> Value {
+        let mut buf = [0; 65_536];
+        let len = daemon.recv(&mut buf).expect("no packet received");
+        let packet = std::str::from_utf8(&buf[..len]).unwrap();
+        let (header, document) = packet.split_once('\n').unwrap();
+        assert_eq!(header, "{\"format\":\"json\",\"version\":1}");
+        serde_json::from_str(document).unwrap()
+    }
+
+    #[test]
+    fn reads_the_daemon_address_from_env_vars() {
+        let env_vars = LambdaRuntimeEnv::builder()
+            .with_var(XRAY_DAEMON_ADDRESS, "169.254.79.129:2000")
+            .build();
+        let emitter = Emitter::from_env_vars(&env_vars).unwrap();
+        assert_eq!(emitter.address, "169.254.79.129:2000".parse().unwrap());
+
+        let env_vars = LambdaRuntimeEnv::builder().build();
+        assert_eq!(
+            Emitter::from_env_vars(&env_vars).unwrap_err().to_string(),
+            "AWS_XRAY_DAEMON_ADDRESS is not set"
+        );
+    }
+
+    #[test]
+    fn sends_sampled_subsegments_to_the_daemon() {
+        let daemon = UdpSocket::bind("127.0.0.1:0").unwrap();
+        daemon
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = format!("tcp:127.0.0.1:2000 udp:{}", daemon.local_addr().unwrap());
+        let mut recorder = Recorder::new(Emitter::new(&address).unwrap());
+
+        // Nothing is sent for traces that are not sampled, the first packet must belong to the sampled trace.
+        recorder.begin_invocation(Some(&format!(
+            "Root={};Parent={};Sampled=0",
+            NOT_SAMPLED_ROOT, PARENT
+        )));
+        begin_subsegment("not-sampled").unwrap().end().unwrap();
+        recorder.end_invocation(false);
+
+        recorder.begin_invocation(Some(&format!(
+            "Root={};Parent={};Sampled=1",
+            SAMPLED_ROOT, PARENT
+        )));
+        let invocation_id = current_trace_header().unwrap().parent.unwrap();
+        let mut downstream = begin_subsegment("downstream").unwrap();
+        downstream.set_namespace("remote");
+        downstream.add_annotation("attempt", 1);
+        let downstream_id = downstream.id().to_string();
+        downstream.end().unwrap();
+        recorder.end_invocation(true);
+
+        let document = receive(&daemon);
+        assert_eq!(document["name"], "downstream");
+        assert_eq!(document["id"], downstream_id.as_str());
+        assert_eq!(document["trace_id"], SAMPLED_ROOT);
+        assert_eq!(document["parent_id"], invocation_id.as_str());
+        assert_eq!(document["type"], "subsegment");
+        assert_eq!(document["namespace"], "remote");
+        assert_eq!(document["annotations"], json!({"attempt": 1}));
+
+        let document = receive(&daemon);
+        assert_eq!(document["name"], INVOCATION_SUBSEGMENT);
+        assert_eq!(document["id"], invocation_id.as_str());
+        assert_eq!(document["trace_id"], SAMPLED_ROOT);
+        assert_eq!(document["parent_id"], PARENT);
+        assert_eq!(document["fault"], true);
+        assert!(document["end_time"].as_f64() >= document["start_time"].as_f64());
+
+        daemon
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(daemon.recv(&mut [0; 16]).is_err());
+        assert!(begin_subsegment("outside").is_none());
+    }
+}